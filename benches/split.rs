@@ -0,0 +1,105 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rectangle_occlusion::scene::*;
+use rectangle_occlusion::{FrontToBackBuilder, GridFrontToBackBuilder, ZeroEpsilon};
+use euclid::default::Box2D;
+use euclid::{point2, UnknownUnit};
+
+fn scenes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("front_to_back");
+    for &n in &[8, 16, 32] {
+        let inputs = [
+            ("dense_occluders", dense_occluders(n)),
+            ("sparse_occluders", sparse_occluders(n)),
+            ("giant_backdrop", giant_backdrop(n)),
+            ("clustered_occluders", clustered_occluders(n)),
+        ];
+        for (name, scene) in &inputs {
+            let mut builder = FrontToBackBuilder::new();
+            group.bench_with_input(BenchmarkId::new(*name, n), scene, |b, scene| {
+                b.iter(|| {
+                    builder.clear();
+                    run_scene(&mut builder, scene)
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn occupancy_grid(c: &mut Criterion) {
+    let mut group = c.benchmark_group("occupancy_grid");
+    for &n in &[8, 16, 32] {
+        let scene = clustered_occluders(n);
+        let size = n as f32 * 100.0;
+        let viewport = Box2D { min: point2(0.0, 0.0), max: point2(size, size) };
+        for &(name, grid) in &[("naive", None), ("grid", Some(viewport))] {
+            let mut builder = FrontToBackBuilder::new();
+            builder.set_occupancy_grid(grid);
+            group.bench_with_input(BenchmarkId::new(name, n), &scene, |b, scene| {
+                b.iter(|| {
+                    builder.clear();
+                    run_scene(&mut builder, scene)
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+// Finds the number of occluders past which the grid beats the linear scan. With the
+// default grid size, it is between 64 (n = 8) and 256 (n = 16) on this scene.
+fn grid_builder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("grid_builder");
+    for &n in &[8, 16, 32, 64] {
+        let scene = dense_occluders(n);
+        let size = n as f32 * 10.0 + 5.0;
+        let viewport = Box2D { min: point2(0.0, 0.0), max: point2(size, size) };
+
+        let mut builder = FrontToBackBuilder::new();
+        group.bench_with_input(BenchmarkId::new("naive", n), &scene, |b, scene| {
+            b.iter(|| {
+                builder.clear();
+                run_scene(&mut builder, scene)
+            })
+        });
+
+        let mut builder = GridFrontToBackBuilder::new(viewport);
+        group.bench_with_input(BenchmarkId::new("grid", n), &scene, |b, scene| {
+            b.iter(|| {
+                builder.clear();
+                scene.iter().filter(|&&(rect, is_opaque, key)| builder.add(&rect, is_opaque, key)).count()
+            })
+        });
+    }
+    group.finish();
+}
+
+// Compares inline capacities of the fragment buffer with sparse and dense occluders.
+// Small capacities spill to the heap more often while large ones use more stack space,
+// the default of 16 is the fastest of the three on both scenes.
+fn inline_capacity(c: &mut Criterion) {
+    fn bench<const N: usize>(group: &mut criterion::BenchmarkGroup<criterion::measurement::WallTime>, name: &str, scene: &[SceneItem]) {
+        let mut builder: FrontToBackBuilder<u64, ZeroEpsilon, (), UnknownUnit, N> = FrontToBackBuilder::default();
+        group.bench_with_input(BenchmarkId::new(name, N), scene, |b, scene| {
+            b.iter(|| {
+                builder.clear();
+                scene.iter().filter(|&&(rect, is_opaque, key)| builder.add(&rect, is_opaque, key)).count()
+            })
+        });
+    }
+
+    let mut group = c.benchmark_group("inline_capacity");
+    let inputs = [
+        ("sparse_occluders", sparse_occluders(16)),
+        ("dense_occluders", dense_occluders(16)),
+    ];
+    for (name, scene) in &inputs {
+        bench::<4>(&mut group, name, scene);
+        bench::<16>(&mut group, name, scene);
+        bench::<64>(&mut group, name, scene);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scenes, occupancy_grid, grid_builder, inline_capacity);
+criterion_main!(benches);
@@ -0,0 +1,133 @@
+//! Finding the largest axis-aligned rectangle that doesn't overlap a set of obstacles.
+//!
+//! The viewport is split into a grid made of all of the distinct x and y coordinates of
+//! the obstacles that are inside of it. The largest empty rectangle has its edges on these
+//! coordinates, so the search is exact. Each row of the grid is treated as the base of a
+//! histogram of empty cells above it, which is searched with a stack in linear time for an
+//! overall cost of O(n²) for n obstacles.
+
+use crate::Scalar;
+use euclid::default::Box2D;
+use euclid::num::Zero;
+use euclid::point2;
+use std::cmp::Ordering;
+
+/// Returns the rectangle of largest area in the viewport that doesn't overlap any of the
+/// obstacles, or `None` if the viewport is completely covered.
+///
+/// When several rectangles have the same area, the one with the smallest `max.y` is
+/// returned.
+pub fn largest_empty_rect<T: Scalar>(obstacles: &[Box2D<T>], viewport: &Box2D<T>) -> Option<Box2D<T>> {
+    if viewport.is_empty() {
+        return None;
+    }
+
+    let obstacles: Vec<Box2D<T>> = obstacles
+        .iter()
+        .filter_map(|r| r.intersection(viewport))
+        .filter(|r| !r.is_empty())
+        .collect();
+
+    let mut xs = vec![viewport.min.x, viewport.max.x];
+    let mut ys = vec![viewport.min.y, viewport.max.y];
+    for r in &obstacles {
+        xs.extend_from_slice(&[r.min.x, r.max.x]);
+        ys.extend_from_slice(&[r.min.y, r.max.y]);
+    }
+    for v in [&mut xs, &mut ys] {
+        v.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        v.dedup();
+    }
+
+    let index = |v: &[T], x: T| v.binary_search_by(|a| a.partial_cmp(&x).unwrap_or(Ordering::Equal)).unwrap();
+
+    // Cells are indexed by their min corner.
+    let w = xs.len() - 1;
+    let h = ys.len() - 1;
+    let mut covered = vec![false; w * h];
+    for r in &obstacles {
+        for y in index(&ys, r.min.y)..index(&ys, r.max.y) {
+            for x in index(&xs, r.min.x)..index(&xs, r.max.x) {
+                covered[y * w + x] = true;
+            }
+        }
+    }
+
+    // The first row of the run of empty cells that ends at the current row, for each
+    // column. A smaller start means a taller column.
+    let mut start = vec![0; w];
+    let mut stack: Vec<usize> = Vec::with_capacity(w);
+    let mut best = None;
+    let mut best_area = T::Area::zero();
+    for y in 0..h {
+        for x in 0..w {
+            if covered[y * w + x] {
+                start[x] = y + 1;
+            }
+        }
+
+        let max_y = ys[y + 1];
+        stack.clear();
+        for x in 0..=w {
+            // The extra column at the end has a height of zero to empty the stack.
+            let current = if x < w { start[x] } else { y + 1 };
+            while let Some(&top) = stack.last() {
+                if start[top] > current {
+                    break;
+                }
+                stack.pop();
+                if start[top] > y {
+                    continue;
+                }
+
+                let left = stack.last().map_or(0, |&idx| idx + 1);
+                let rect = Box2D {
+                    min: point2(xs[left], ys[start[top]]),
+                    max: point2(xs[x], max_y),
+                };
+                let area = T::area(&rect);
+                if area > best_area {
+                    best = Some(rect);
+                    best_area = area;
+                }
+            }
+            stack.push(x);
+        }
+    }
+
+    best
+}
+
+#[test]
+fn no_obstacle() {
+    let viewport = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 50.0) };
+    let outside = Box2D { min: point2(200.0, 0.0), max: point2(300.0, 50.0) };
+    assert_eq!(largest_empty_rect(&[outside], &viewport), Some(viewport));
+    assert_eq!(largest_empty_rect(&[viewport], &viewport), None);
+}
+
+#[test]
+fn single_obstacle() {
+    let viewport = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+    let obstacle = Box2D { min: point2(30.0, 20.0), max: point2(50.0, 100.0) };
+    assert_eq!(
+        largest_empty_rect(&[obstacle], &viewport),
+        Some(Box2D { min: point2(50.0, 0.0), max: point2(100.0, 100.0) }),
+    );
+}
+
+#[test]
+fn between_obstacles() {
+    let viewport = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+    let obstacles = [
+        Box2D { min: point2(0.0, 0.0), max: point2(100.0, 30.0) },
+        Box2D { min: point2(0.0, 80.0), max: point2(100.0, 100.0) },
+        Box2D { min: point2(0.0, 30.0), max: point2(10.0, 80.0) },
+        Box2D { min: point2(60.0, 40.0), max: point2(70.0, 50.0) },
+    ];
+    // The band below the small obstacle spans the full width.
+    assert_eq!(
+        largest_empty_rect(&obstacles, &viewport),
+        Some(Box2D { min: point2(10.0, 50.0), max: point2(100.0, 80.0) }),
+    );
+}
@@ -0,0 +1,214 @@
+//! A front-to-back builder that indexes its occluders with a uniform grid.
+//!
+//! Each occluder is registered in the cells of the grid it overlaps, so adding a rectangle
+//! only tests the occluders of the cells it touches instead of scanning all of them. This
+//! pays off with thousands of occluders that are spread over the viewport, while the
+//! bookkeeping makes it slower than `FrontToBackBuilder` for small scenes.
+
+use crate::{cull_fragments, is_degenerate, Fragment, FragmentOrigin, Item, Key, TaggedFragment, INLINE_FRAGMENT_CAPACITY};
+use euclid::default::{Box2D, Point2D};
+use euclid::point2;
+use smallvec::SmallVec;
+
+/// The default number of cells on each axis of the grid.
+pub const DEFAULT_GRID_SIZE: usize = 32;
+
+/// A builder that applies occlusion culling with rectangles provided in front-to-back order,
+/// using a uniform grid over a viewport to find the occluders that affect each rectangle.
+///
+/// The output is the same as `FrontToBackBuilder`'s for the same input. Rectangles and
+/// occluders outside of the viewport are still culled correctly but are all registered in
+/// the cells on its border.
+pub struct GridFrontToBackBuilder<K = u64> {
+    opaque_items: Vec<Item<K>>,
+    alpha_items: Vec<Item<K>>,
+    occluders: Vec<Box2D<f32>>,
+    // The indices of the occluders that overlap each cell, in increasing order.
+    cells: Vec<Vec<u32>>,
+    viewport: Box2D<f32>,
+    columns: usize,
+    rows: usize,
+}
+
+impl<K: Key> GridFrontToBackBuilder<K> {
+    /// Constructor with a `DEFAULT_GRID_SIZE` by `DEFAULT_GRID_SIZE` grid.
+    pub fn new(viewport: Box2D<f32>) -> Self {
+        Self::with_grid_size(viewport, DEFAULT_GRID_SIZE, DEFAULT_GRID_SIZE)
+    }
+
+    /// Constructor with a custom number of cells on each axis.
+    pub fn with_grid_size(viewport: Box2D<f32>, columns: usize, rows: usize) -> Self {
+        assert!(columns > 0 && rows > 0);
+        GridFrontToBackBuilder {
+            opaque_items: Vec::new(),
+            alpha_items: Vec::new(),
+            occluders: Vec::new(),
+            cells: vec![Vec::new(); columns * rows],
+            viewport,
+            columns,
+            rows,
+        }
+    }
+
+    /// Add a rectangle, potentially splitting it and discarding the occluded parts if any.
+    ///
+    /// Returns true the rectangle is at least partially visible. Like with
+    /// `FrontToBackBuilder::add`, empty and non-finite rectangles are never visible.
+    pub fn add(&mut self, rect: &Box2D<f32>, is_opaque: bool, key: K) -> bool {
+        let mut fragments: SmallVec<[TaggedFragment; INLINE_FRAGMENT_CAPACITY]> = SmallVec::new();
+        self.cull(rect, &mut fragments);
+
+        let list = if is_opaque { &mut self.opaque_items } else { &mut self.alpha_items };
+        for fragment in &fragments {
+            list.push(Item {
+                rectangle: fragment.rect,
+                original: *rect,
+                origin: fragment.origin,
+                key,
+                data: (),
+            });
+        }
+
+        if is_opaque {
+            for fragment in &fragments {
+                self.add_occluder(&fragment.rect);
+            }
+        }
+
+        !fragments.is_empty()
+    }
+
+    /// Returns true if the provided rect is at least partially visible, without adding it.
+    pub fn test(&self, rect: &Box2D<f32>) -> bool {
+        let mut fragments: SmallVec<[Box2D<f32>; INLINE_FRAGMENT_CAPACITY]> = SmallVec::new();
+        self.cull(rect, &mut fragments);
+
+        !fragments.is_empty()
+    }
+
+    /// The visible opaque rectangles (front-to-back order).
+    pub fn opaque_items(&self) -> &[Item<K>] {
+        &self.opaque_items
+    }
+
+    /// The visible blended rectangles (front-to-back order).
+    pub fn alpha_items(&self) -> &[Item<K>] {
+        &self.alpha_items
+    }
+
+    /// The opaque and alpha items.
+    pub fn items(&self) -> (&[Item<K>], &[Item<K>]) {
+        (&self.opaque_items, &self.alpha_items)
+    }
+
+    /// Removes all items and occluders, preserving memory allocations.
+    pub fn clear(&mut self) {
+        self.opaque_items.clear();
+        self.alpha_items.clear();
+        self.occluders.clear();
+        for cell in &mut self.cells {
+            cell.clear();
+        }
+    }
+
+    fn add_occluder(&mut self, rect: &Box2D<f32>) {
+        let index = self.occluders.len() as u32;
+        self.occluders.push(*rect);
+        let (min, max) = self.cell_range(rect);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                self.cells[y * self.columns + x].push(index);
+            }
+        }
+    }
+
+    // Test the rect against the occluders of the cells it touches, in the order they
+    // were added so that the splits match the ones of `FrontToBackBuilder`.
+    fn cull<F: Fragment>(&self, rect: &Box2D<f32>, fragments: &mut SmallVec<[F; INLINE_FRAGMENT_CAPACITY]>) {
+        if is_degenerate(rect) {
+            return;
+        }
+
+        let (min, max) = self.cell_range(rect);
+        let mut candidates: SmallVec<[u32; 64]> = SmallVec::new();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                candidates.extend_from_slice(&self.cells[y * self.columns + x]);
+            }
+        }
+        if min != max {
+            candidates.sort_unstable();
+            candidates.dedup();
+        }
+
+        if candidates.is_empty() {
+            fragments.push(F::new(*rect, FragmentOrigin::Unsplit));
+            return;
+        }
+
+        let occluders = candidates.iter().map(|&i| self.occluders[i as usize]);
+        cull_fragments(occluders, rect, fragments);
+    }
+
+    // The cells touched by the rect. Coordinates are clamped to the viewport so that
+    // rectangles outside of it map to the cells on its border, which keeps the test
+    // conservative.
+    fn cell_range(&self, rect: &Box2D<f32>) -> (Point2D<usize>, Point2D<usize>) {
+        let v = &self.viewport;
+        let cell = |x: f32, min: f32, size: f32, count: usize| -> usize {
+            if size <= 0.0 {
+                return 0;
+            }
+            (((x - min) / size * count as f32).max(0.0) as usize).min(count - 1)
+        };
+        let min = point2(
+            cell(rect.min.x, v.min.x, v.width(), self.columns),
+            cell(rect.min.y, v.min.y, v.height(), self.rows),
+        );
+        let max = point2(
+            cell(rect.max.x, v.min.x, v.width(), self.columns),
+            cell(rect.max.y, v.min.y, v.height(), self.rows),
+        );
+
+        (min, max)
+    }
+}
+
+#[test]
+fn matches_front_to_back_builder() {
+    use crate::FrontToBackBuilder;
+
+    let viewport = Box2D { min: point2(0.0, 0.0), max: point2(200.0, 200.0) };
+    let mut grid = GridFrontToBackBuilder::with_grid_size(viewport, 4, 4);
+    let mut reference = FrontToBackBuilder::new();
+
+    let mut rects = Vec::new();
+    for i in 0..40 {
+        let x = (i * 37 % 230) as f32 - 20.0;
+        let y = (i * 53 % 230) as f32 - 20.0;
+        let size = (10 + i * 7 % 60) as f32;
+        let rect = Box2D { min: point2(x, y), max: point2(x + size, y + size) };
+        rects.push((rect, i % 3 != 0, i as u64));
+    }
+    // Empty and inverted rectangles, which also skip the candidates of their cells.
+    rects.push((Box2D { min: point2(50.0, 50.0), max: point2(50.0, 80.0) }, true, 40));
+    rects.push((Box2D { min: point2(80.0, 80.0), max: point2(60.0, 90.0) }, true, 41));
+    rects.push((Box2D { min: point2(-30.0, 10.0), max: point2(-40.0, 5.0) }, false, 42));
+    if !cfg!(debug_assertions) {
+        // Non-finite coordinates trigger an assertion in debug builds.
+        rects.push((Box2D { min: point2(0.0, 0.0), max: point2(f32::INFINITY, 10.0) }, true, 43));
+        rects.push((Box2D { min: point2(f32::NAN, 0.0), max: point2(10.0, 10.0) }, false, 44));
+        rects.push((Box2D { min: point2(f32::NEG_INFINITY, 0.0), max: point2(10.0, 10.0) }, true, 45));
+    }
+
+    for &(rect, is_opaque, key) in &rects {
+        assert_eq!(grid.test(&rect), reference.test(&rect));
+        assert_eq!(grid.add(&rect, is_opaque, key), reference.add(&rect, is_opaque, key));
+    }
+
+    assert_eq!(grid.opaque_items(), reference.opaque_items());
+    assert_eq!(grid.alpha_items(), reference.alpha_items());
+
+    grid.clear();
+    assert!(grid.add(&viewport, true, 0));
+}
@@ -37,6 +37,7 @@
 //! This implementation favors longer horizontal bands instead creating nine-patches to deal with the corners.
 //! The advantage is that it produces less rectangles which is good for the performance of the algorithm, however
 //! it could cause artifacts if the resulting rectangles are drawn with a non-axis-aligned transformation.
+//! Other ways to split rectangles can be selected with `SplitStrategy`.
 //!
 //! ## Performance
 //!
@@ -53,327 +54,5248 @@
 //! occluders.
 //!
 
+mod empty_rect;
+mod grid;
+mod outline;
+mod recorder;
+pub mod scene;
+#[cfg(any(test, feature = "validation"))]
+mod validation;
+
+pub use grid::{GridFrontToBackBuilder, DEFAULT_GRID_SIZE};
+pub use recorder::{replay, Recorder};
+#[cfg(any(test, feature = "validation"))]
+pub use validation::{fuzz_build, verify_against_reference};
+pub use svg_fmt;
+
 use euclid::default::*;
-use euclid::point2;
-use smallvec::SmallVec;
+use euclid::num::{Ceil, Floor, Round, Zero};
+use euclid::{point2, vec2, UnknownUnit};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use smallvec::{Array, SmallVec};
 
-/// A visible part of a rectangle after occlusion culling.
-#[derive(Debug, PartialEq)]
-pub struct Item {
-    pub rectangle: Box2D<f32>,
-    pub key: u64,
-}
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::Sum;
+use std::marker::PhantomData;
+use std::ops::{Add, DerefMut, Sub};
 
-/// A builder that applies occlusion culling with rectangles provided in front-to-back order.
+/// The number of fragments that are stored inline while splitting a rectangle.
+///
+/// Splitting a rectangle into more visible parts than this requires a temporary heap
+/// allocation. This is the default for `FrontToBackBuilder`, see its `N` parameter.
+pub const INLINE_FRAGMENT_CAPACITY: usize = 16;
+
+/// A visible part of a rectangle after occlusion culling.
 ///
-/// It is faster than `BackToFrontBuilder`.
-pub struct FrontToBackBuilder {
-    opaque_items: Vec<Item>,
-    alpha_items: Vec<Item>,
+/// `U` is the unit of the coordinates and `T` their type, see `Scalar`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "K: Serialize, D: Serialize, T: Serialize",
+        deserialize = "K: Deserialize<'de>, D: Deserialize<'de>, T: Deserialize<'de>"
+    ))
+)]
+pub struct Item<K = u64, D = (), U = UnknownUnit, T = f32> {
+    pub rectangle: euclid::Box2D<T, U>,
+    /// The rectangle this item was split from.
+    ///
+    /// This is useful to compute the texture coordinates of the fragment.
+    pub original: euclid::Box2D<T, U>,
+    /// Where this item is relative to the last occluder that split it.
+    pub origin: FragmentOrigin,
+    pub key: K,
+    /// User data copied from the rectangle this item was split from.
+    pub data: D,
 }
 
-impl FrontToBackBuilder {
-    /// Constructor.
-    pub fn new() -> Self {
-        FrontToBackBuilder {
-            opaque_items: Vec::new(),
-            alpha_items: Vec::new(),
+// Implemented manually so that the unit doesn't have to implement the traits.
+impl<K: Clone, D: Clone, U, T: Copy> Clone for Item<K, D, U, T> {
+    fn clone(&self) -> Self {
+        Item {
+            rectangle: self.rectangle,
+            original: self.original,
+            origin: self.origin,
+            key: self.key.clone(),
+            data: self.data.clone(),
         }
     }
+}
 
-    /// Pre-allocating constructor.
-    pub fn with_capacity(opaque: usize, alpha: usize) -> Self {
-        FrontToBackBuilder {
-            opaque_items: Vec::with_capacity(opaque),
-            alpha_items: Vec::with_capacity(alpha),
-        }
+impl<K: Copy, D: Copy, U, T: Copy> Copy for Item<K, D, U, T> {}
+
+impl<K: fmt::Debug, D: fmt::Debug, U, T: fmt::Debug> fmt::Debug for Item<K, D, U, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Item")
+            .field("rectangle", &self.rectangle)
+            .field("original", &self.original)
+            .field("origin", &self.origin)
+            .field("key", &self.key)
+            .field("data", &self.data)
+            .finish()
     }
+}
 
-    /// Add a rectangle, potentially splitting it and discarding the occluded parts if any.
+impl<K: PartialEq, D: PartialEq, U, T: PartialEq> PartialEq for Item<K, D, U, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.rectangle == other.rectangle
+            && self.original == other.original
+            && self.origin == other.origin
+            && self.key == other.key
+            && self.data == other.data
+    }
+}
+
+impl<K: Copy, D, U, T: Scalar> Item<K, D, U, T> {
+    /// Returns the key and the bit patterns of the coordinates of the visible rectangle
+    /// as `[min.x, min.y, max.x, max.y]`, as given by [`Scalar::to_bits`].
     ///
-    /// Returns true the rectangle is at least partially visible.
-    pub fn add(&mut self, rect: &Box2D<f32>, is_opaque: bool, key: u64) -> bool {
-        let mut fragments: SmallVec<[Box2D<f32>; 16]> = SmallVec::new();
-        fragments.push(*rect);
+    /// Unlike the item itself, this can be hashed and compared for equality, for example to
+    /// find the items that changed between two frames. Coordinates are compared bit for bit,
+    /// so `0.0` and `-0.0` are considered different, as are any two rectangles that differ by
+    /// a rounding error.
+    pub fn key_and_bits(&self) -> (K, [u64; 4]) {
+        let r = &self.rectangle;
+        (self.key, [r.min.x.to_bits(), r.min.y.to_bits(), r.max.x.to_bits(), r.max.y.to_bits()])
+    }
+}
 
-        for item in &self.opaque_items {
-            if fragments.is_empty() {
-                break;
-            }
-            if item.rectangle.intersects(rect) {
-                apply_occluder(&item.rectangle, &mut fragments);
+/// The items produced by a builder, for example to store them as golden files.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "K: Serialize, D: Serialize, T: Serialize",
+    deserialize = "K: Deserialize<'de>, D: Deserialize<'de>, T: Deserialize<'de>"
+))]
+pub struct Snapshot<K = u64, D = (), U = UnknownUnit, T = f32> {
+    pub opaque_items: Vec<Item<K, D, U, T>>,
+    pub alpha_items: Vec<Item<K, D, U, T>>,
+}
+
+/// The side of the occluder a fragment was produced on when splitting a rectangle.
+///
+/// This is useful to adjust texture sampling at the split boundaries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FragmentOrigin {
+    /// The rectangle wasn't split.
+    Unsplit,
+    /// The band above the occluder, spanning the width of the split rectangle.
+    Top,
+    /// The band below the occluder, spanning the width of the split rectangle.
+    Bottom,
+    /// The part on the left of the occluder, between the top and bottom bands.
+    Left,
+    /// The part on the right of the occluder, between the top and bottom bands.
+    Right,
+}
+
+/// The user-provided value associated to each rectangle and copied into the items produced
+/// from it.
+///
+/// Use `()` if keys aren't needed, to avoid storing them.
+pub trait Key: Copy {
+    /// A value used to pick the color of the item in debugging output.
+    ///
+    /// If `None`, the position of the item in its list is used instead.
+    fn debug_seed(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl Key for () {}
+
+macro_rules! impl_integer_key {
+    ($($ty:ty),*) => {
+        $(
+            impl Key for $ty {
+                fn debug_seed(&self) -> Option<u64> {
+                    Some(*self as u64)
+                }
             }
-        }
+        )*
+    };
+}
 
-        let list = if is_opaque {
-            &mut self.opaque_items
-        } else {
-            &mut self.alpha_items
-        };
+impl_integer_key!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
 
-        for rect in &fragments {
-            list.push(Item {
-                rectangle: *rect,
-                key,
-            });
+/// The error returned when trying to create an `AxisAlignedTransform` out of a transform
+/// that does not map axis-aligned rectangles to axis-aligned rectangles.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NonAxisAlignedTransform;
+
+impl fmt::Display for NonAxisAlignedTransform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the transform is not axis-aligned")
+    }
+}
+
+impl std::error::Error for NonAxisAlignedTransform {}
+
+/// A transform that maps axis-aligned rectangles to axis-aligned rectangles.
+///
+/// This covers translations, scales, flips and rotations by multiples of 90 degrees.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AxisAlignedTransform {
+    transform: Transform2D<f32>,
+    inverse: Transform2D<f32>,
+}
+
+impl AxisAlignedTransform {
+    /// The identity transform.
+    pub fn identity() -> Self {
+        AxisAlignedTransform {
+            transform: Transform2D::identity(),
+            inverse: Transform2D::identity(),
         }
+    }
 
-        !fragments.is_empty()
+    /// Returns an error if the transform isn't axis-aligned or can't be inverted.
+    pub fn new(transform: &Transform2D<f32>) -> Result<Self, NonAxisAlignedTransform> {
+        let t = transform;
+        let scale_only = t.m12 == 0.0 && t.m21 == 0.0;
+        let axis_swap = t.m11 == 0.0 && t.m22 == 0.0;
+        if !scale_only && !axis_swap {
+            return Err(NonAxisAlignedTransform);
+        }
+
+        let inverse = t.inverse().ok_or(NonAxisAlignedTransform)?;
+
+        Ok(AxisAlignedTransform {
+            transform: *t,
+            inverse,
+        })
     }
 
-    /// Returns true if the provided rect is at least partially visible, without adding it.
-    pub fn test(&self, rect: &Box2D<f32>) -> bool {
-        let mut fragments: SmallVec<[Box2D<f32>; 16]> = SmallVec::new();
-        fragments.push(*rect);
+    /// A rotation by a multiple of 90 degrees around the origin.
+    ///
+    /// Unlike `Transform2D::rotation`, this is exact and doesn't suffer from the
+    /// imprecision of computing the sine and cosine of the angle.
+    pub fn rotation(quarter_turns: i32) -> Self {
+        let (cos, sin) = match quarter_turns.rem_euclid(4) {
+            0 => (1.0, 0.0),
+            1 => (0.0, 1.0),
+            2 => (-1.0, 0.0),
+            _ => (0.0, -1.0),
+        };
 
-        for item in &self.opaque_items {
-            if item.rectangle.intersects(rect) {
-                apply_occluder(&item.rectangle, &mut fragments);
-            }
+        AxisAlignedTransform {
+            transform: Transform2D::new(cos, sin, -sin, cos, 0.0, 0.0),
+            inverse: Transform2D::new(cos, -sin, sin, cos, 0.0, 0.0),
         }
+    }
 
-        !fragments.is_empty()
+    /// Maps a rectangle from the user space to the culling space.
+    pub fn transform_box(&self, b: &Box2D<f32>) -> Box2D<f32> {
+        map_box(&self.transform, b)
     }
 
-    /// The visible opaque rectangles (front-to-back order).
-    pub fn opaque_items(&self) -> &[Item] {
-        &self.opaque_items
+    /// Maps a rectangle from the culling space back to the user space.
+    pub fn inverse_transform_box(&self, b: &Box2D<f32>) -> Box2D<f32> {
+        map_box(&self.inverse, b)
     }
 
-    /// The visible non-opaque rectangles (front-to-back order).
-    pub fn alpha_items(&self) -> &[Item] {
-        &self.alpha_items
+    /// Maps the side a fragment was produced on from the culling space back to the user space.
+    pub fn inverse_transform_origin(&self, origin: FragmentOrigin) -> FragmentOrigin {
+        let v = match origin {
+            FragmentOrigin::Unsplit => return origin,
+            FragmentOrigin::Top => vec2(0.0, -1.0),
+            FragmentOrigin::Bottom => vec2(0.0, 1.0),
+            FragmentOrigin::Left => vec2(-1.0, 0.0),
+            FragmentOrigin::Right => vec2(1.0, 0.0),
+        };
+
+        let v = self.inverse.transform_vector(v);
+        if v.x.abs() > v.y.abs() {
+            if v.x < 0.0 { FragmentOrigin::Left } else { FragmentOrigin::Right }
+        } else if v.y < 0.0 {
+            FragmentOrigin::Top
+        } else {
+            FragmentOrigin::Bottom
+        }
     }
+}
 
-    /// Resets the builder to its initial state, preserving memory allocations.
-    pub fn clear(&mut self) {
-        self.opaque_items.clear();
-        self.alpha_items.clear();
+fn map_box(t: &Transform2D<f32>, b: &Box2D<f32>) -> Box2D<f32> {
+    let a = t.transform_point(b.min);
+    let b = t.transform_point(b.max);
+    Box2D {
+        min: a.min(b),
+        max: a.max(b),
     }
+}
 
-    pub fn dump_as_svg(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
-        use svg_fmt::*;
+/// The error returned by `FrontToBackBuilder::try_add` when storing the visible parts of
+/// a rectangle would exceed the item budget.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CapacityExceeded;
 
-        let mut w: f32 = 0.0;
-        let mut h: f32 = 0.0;
+impl fmt::Display for CapacityExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the item budget was exceeded")
+    }
+}
 
-        for item in &self.opaque_items {
-            w = w.max(item.rectangle.max.x);
-            h = h.max(item.rectangle.max.y);
-        }
-        for item in &self.alpha_items {
-            w = w.max(item.rectangle.max.x);
-            h = h.max(item.rectangle.max.y);
-        }
+impl std::error::Error for CapacityExceeded {}
 
-        writeln!(output, "{}", BeginSvg { w, h } )?;
+/// The error returned by `BackToFrontBuilder::build_cancellable` when the build was
+/// cancelled.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cancelled;
 
-        // Use random blue-ish colors for opaque items and and random red-ish colors for
-        // non-opaque ones. The colors are seeded from the item key.
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the build was cancelled")
+    }
+}
 
-        for item in &self.opaque_items {
-            let i = ((item.key * 37) % 100) as u8;
-            let color = rgb(0, i, 150 + i);
-            let r = item.rectangle;
+impl std::error::Error for Cancelled {}
+
+/// The error returned when a builder is configured with parameters that would make it cull
+/// everything, see `FrontToBackBuilder::set_clip`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BuilderError {
+    /// The minimum of the clip rectangle is greater than its maximum on one of the axes.
+    InvertedClip,
+    /// The clip rectangle has a zero width or height.
+    EmptyClip,
+    /// The clip rectangle has infinite or NaN coordinates.
+    NonFiniteClip,
+}
 
-            writeln!(
-                output,
-                r#"    {}"#,
-                rectangle(r.min.x, r.min.y, r.size().width, r.size().height).fill(color).stroke(Stroke::Color(black(), 1.0))
-            )?;
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuilderError::InvertedClip => write!(f, "the clip rectangle is inverted"),
+            BuilderError::EmptyClip => write!(f, "the clip rectangle is empty"),
+            BuilderError::NonFiniteClip => write!(f, "the clip rectangle is not finite"),
         }
+    }
+}
 
-        for item in &self.alpha_items {
-            let i = ((item.key * 37) % 100) as u8;
-            let color = rgb(150 + i, i, 0);
-            let r = item.rectangle;
+impl std::error::Error for BuilderError {}
 
-            writeln!(
-                output,
-                r#"    {}"#,
-                rectangle(r.min.x, r.min.y, r.size().width, r.size().height)
-                    .fill(color)
-                    .opacity(0.6)
-                    .stroke(Stroke::Color(black(), 1.0))
-            )?;
-        }
+/// Specifies how a rectangle is rendered and whether it occludes the rectangles behind it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ItemKind {
+    /// Rendered in the opaque pass and occludes rectangles behind it.
+    Opaque,
+    /// Rendered in the alpha pass.
+    Alpha,
+    /// Rendered in the opaque pass but doesn't occlude anything, for example because
+    /// its shader discards some of the pixels.
+    ///
+    /// Because they don't occlude, these items can overlap opaque items that are
+    /// behind them, so the opaque pass is no longer order-independent when they are used.
+    OpaqueNonOccluding,
+    /// Occludes rectangles behind it but is not rendered, for example a proxy for content
+    /// that is drawn by other means.
+    OccluderOnly,
+}
 
-        writeln!(output, "{}", EndSvg)    }
+/// Specifies which opaque rectangles are kept as occluders when their number is capped
+/// with `FrontToBackBuilder::set_max_occluders`.
+///
+/// The policy only affects how many of the rectangles behind the occluders are culled.
+/// Opaque rectangles that are not kept as occluders don't cull the opaque rectangles added
+/// after them, so the opaque items can overlap once the cap is reached. Like with
+/// `ItemKind::OpaqueNonOccluding`, the opaque pass then needs to be rendered front-to-back
+/// with a depth test.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OccluderPolicy {
+    /// Keep the front-most occluders and ignore the ones added after the cap is reached.
+    FrontMost,
+    /// Keep the occluders with the largest area, replacing the smallest one when a larger
+    /// opaque rectangle is added.
+    ///
+    /// Rectangles that are directly behind an evicted occluder are no longer culled by it,
+    /// including opaque ones which then overlap the evicted occluder's item. Replacing an
+    /// occluder also requires scanning the set, which makes adding opaque rectangles more
+    /// expensive once the cap is reached.
+    LargestArea,
 }
 
+/// A buffer for the fragments of the rectangles added with
+/// `FrontToBackBuilder::add_with_scratch`.
+///
+/// Keeping one alive across many calls amortizes the allocations of rectangles that are
+/// split into more than `INLINE_FRAGMENT_CAPACITY` parts.
+#[derive(Clone, Debug, Default)]
+pub struct FragmentScratch<T = f32> {
+    fragments: Vec<TaggedFragment<T>>,
+}
 
-// Split out the parts of the rects in the provided vector
-fn apply_occluder(occluder: &Box2D<f32>, rects: &mut SmallVec<[Box2D<f32>; 16]>) {
-    // Iterate in reverse order so that we can push new rects at the back without
-    // visiting them;
-    let mut i = rects.len() - 1;
-    loop {
-        let r = rects[i];
-
-        if r.intersects(occluder) {
-            let top = r.min.y < occluder.min.y && r.max.y > occluder.min.y;
-            let bottom = r.max.y > occluder.max.y && r.min.y < occluder.max.y;
-            let left = r.min.x < occluder.min.x && r.max.x > occluder.min.x;
-            let right = r.max.x > occluder.max.x && r.min.x < occluder.max.x;
-
-            if top {
-                rects.push(Box2D {
-                    min: r.min,
-                    max: point2(r.max.x, occluder.min.y),
-                });
-            }
+impl<T> FragmentScratch<T> {
+    /// Constructor.
+    pub fn new() -> Self {
+        FragmentScratch { fragments: Vec::new() }
+    }
 
-            if bottom {
-                rects.push(Box2D {
-                    min: point2(r.min.x, occluder.max.y),
-                    max: r.max,
-                });
-            }
+    /// Pre-allocating constructor.
+    pub fn with_capacity(cap: usize) -> Self {
+        FragmentScratch { fragments: Vec::with_capacity(cap) }
+    }
+}
 
-            if left {
-                let min_y = r.min.y.max(occluder.min.y);
-                let max_y = r.max.y.min(occluder.max.y);
-                rects.push(Box2D {
-                    min: point2(r.min.x, min_y),
-                    max: point2(occluder.min.x, max_y),
-                });
-            }
+/// Information about a rectangle added with `FrontToBackBuilder::add_detailed`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AddResult {
+    /// Whether the rectangle is at least partially visible.
+    pub visible: bool,
+    /// The number of items the visible part of the rectangle was split into, zero if it is
+    /// fully occluded.
+    pub fragment_count: usize,
+}
 
-            if right {
-                let min_y = r.min.y.max(occluder.min.y);
-                let max_y = r.max.y.min(occluder.max.y);
-                rects.push(Box2D {
-                    min: point2(occluder.max.x, min_y),
-                    max: point2(r.max.x, max_y),
-                });
-            }
+/// Specifies how a rectangle is split around an occluder.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// Bands above and below the occluder span the width of the rectangle, the parts on
+    /// the left and right are between them.
+    ///
+    /// This produces the fewest rectangles.
+    #[default]
+    HorizontalBands,
+    /// Bands on the left and right of the occluder span the height of the rectangle, the
+    /// parts above and below are between them.
+    VerticalBands,
+    /// The rectangle is split along all four edges of the occluder into up to 8 parts.
+    ///
+    /// Adjacent parts always share a full edge, which avoids the seams that T-junctions
+    /// between bands can cause when the output is drawn with a non-axis-aligned
+    /// transformation. The corners are tagged as `FragmentOrigin::Top` or `Bottom`.
+    NinePatch,
+}
 
-            // Remove the original rectangle, replacing it with
-            // one of the new ones we just added, or popping it
-            // if it is the last item.
-            if i == rects.len() {
-                rects.pop();
-            } else {
-                rects.swap_remove(i);
-            }
-        }
+/// Specifies how output coordinates are rounded by `round_output`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RoundMode {
+    /// Round all coordinates down.
+    Floor,
+    /// Round all coordinates up.
+    Ceil,
+    /// Round all coordinates to the nearest integer.
+    Nearest,
+    /// Round the minimum down and the maximum up so no pixel is left uncovered.
+    ///
+    /// Unlike the other modes, this can make adjacent items overlap by up to a pixel.
+    Conservative,
+}
 
-        if i == 0 {
-            break;
+impl RoundMode {
+    fn apply<T: Scalar, U>(self, rect: &euclid::Box2D<T, U>) -> euclid::Box2D<T, U> {
+        match self {
+            RoundMode::Floor => euclid::Box2D::new(rect.min.floor(), rect.max.floor()),
+            RoundMode::Ceil => euclid::Box2D::new(rect.min.ceil(), rect.max.ceil()),
+            RoundMode::Nearest => rect.round(),
+            RoundMode::Conservative => rect.round_out(),
         }
-
-        i -= 1;
     }
 }
 
-/// A back-to-front occlusion culling builder provided for convenience.
+/// The tolerance used when comparing the edges of rectangles and occluders.
 ///
-/// This builder internally reconstructs front-to-back order at the cost
-/// of some computation overhead and uses FrontToBackBuilder. For maximum
-/// speed it is better to use `FrontToBackBuilder` directly instead.
-pub struct BackToFrontBuilder {
-    commands: Vec<(Box2D<f32>, bool, u64)>,
-    opaque_items: Vec<Item>,
-    alpha_items: Vec<Item>,
+/// Edges that are closer than `EPS` are considered to be touching, so an occluder that
+/// almost lines up with a rectangle doesn't leave a sliver of it visible. The value is
+/// selected at compile time through the builder's type.
+pub trait Epsilon {
+    const EPS: f32;
 }
 
-impl BackToFrontBuilder {
-    /// Constructor.
-    pub fn new() -> Self {
-        BackToFrontBuilder {
-            commands: Vec::new(),
-            opaque_items: Vec::new(),
-            alpha_items: Vec::new(),
+/// Exact edge comparisons.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ZeroEpsilon;
+
+impl Epsilon for ZeroEpsilon {
+    const EPS: f32 = 0.0;
+}
+
+/// The type of the coordinates of the rectangles, see `ScalarFrontToBackBuilder`.
+///
+/// Integer coordinates make the culling exact and `f64` coordinates keep their precision
+/// past 2^24, at the cost of the conversions `f32` output would need. Splitting only
+/// compares and copies coordinates, so it can't overflow.
+pub trait Scalar:
+    Copy + PartialOrd + Add<Output = Self> + Sub<Output = Self> + Zero + Round + Floor + Ceil + fmt::Debug
+{
+    /// The type areas are computed and accumulated in.
+    ///
+    /// It is `f64` for floating point coordinates, `i64` for `i16` and `i128` for `i32`
+    /// and `i64`. The area of an `i64` rectangle overflows only if it spans more than
+    /// about 2^63 on both axes.
+    type Area: Copy + PartialOrd + Add<Output = Self::Area> + Sub<Output = Self::Area> + Zero + Sum + fmt::Debug;
+
+    /// Converts the tolerance of an `Epsilon`, rounding it down for integer types.
+    fn from_epsilon(eps: f32) -> Self;
+
+    /// Whether the value is neither infinite nor NaN, always true for integer types.
+    fn is_finite(self) -> bool;
+
+    /// The area of the rectangle, computed without overflowing the coordinate type.
+    fn area<U>(rect: &euclid::Box2D<Self, U>) -> Self::Area;
+
+    /// Converts an area to `f64`, for example to compute a ratio.
+    fn area_to_f64(area: Self::Area) -> f64;
+
+    /// Converts the value to `f64`, which can lose precision for `i64`.
+    fn to_f64(self) -> f64;
+
+    /// Converts from `f64`, rounding to the nearest integer for integer types.
+    fn from_f64(value: f64) -> Self;
+
+    /// The bit pattern of the value, used to detect changes.
+    fn to_bits(self) -> u64;
+
+    /// Maps a rectangle with an axis-aligned transform.
+    ///
+    /// The default implementation computes the mapping in `f64`.
+    fn transform_box(transform: &Transform2D<f32>, rect: &Box2D<Self>) -> Box2D<Self> {
+        let t = transform.cast::<f64>();
+        let a = t.transform_point(point2(rect.min.x.to_f64(), rect.min.y.to_f64()));
+        let b = t.transform_point(point2(rect.max.x.to_f64(), rect.max.y.to_f64()));
+        let (min, max) = (a.min(b), a.max(b));
+        Box2D {
+            min: point2(Self::from_f64(min.x), Self::from_f64(min.y)),
+            max: point2(Self::from_f64(max.x), Self::from_f64(max.y)),
         }
     }
+}
 
-    /// Add a rectangle in back-to-font order.
-    ///
-    /// Computation is deferred to the `build()` method.
-    pub fn add(&mut self, rect: &Box2D<f32>, is_opaque: bool, key: u64) {
-        self.commands.push((*rect, is_opaque, key));
+impl Scalar for f32 {
+    type Area = f64;
+
+    fn from_epsilon(eps: f32) -> Self {
+        eps
     }
 
-    /// Apply the occlusion culling algorithm to the rectangles provided by prior `add`
-    /// invocations.
-    pub fn build(&mut self) {
-        let cap = self.commands.len();
-        self.opaque_items.clear();
-        self.opaque_items.reserve(cap);
-        self.alpha_items.clear();
-        self.alpha_items.reserve(cap);
+    fn is_finite(self) -> bool {
+        f32::is_finite(self)
+    }
 
-        let mut builder = FrontToBackBuilder {
-            opaque_items: std::mem::take(&mut self.opaque_items),
-            alpha_items: std::mem::take(&mut self.alpha_items),
-        };
+    fn area<U>(rect: &euclid::Box2D<Self, U>) -> f64 {
+        rect.width() as f64 * rect.height() as f64
+    }
 
-        for cmd in self.commands.iter().rev() {
-            builder.add(&cmd.0, cmd.1, cmd.2);
-        }
+    fn area_to_f64(area: f64) -> f64 {
+        area
+    }
 
-        self.opaque_items = builder.opaque_items;
-        self.alpha_items = builder.alpha_items;
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
 
-        // No need to reverse the opaque list because it does not
-        // matter for rendering.
-        self.alpha_items.reverse();
-        self.commands.clear();
+    fn from_f64(value: f64) -> Self {
+        value as f32
     }
 
-    /// The visible opaque rectangles.
-    ///
-    /// Opaque items are only accessible after `build()`.
-    pub fn opaque_items(&self) -> &[Item] {
-        &self.opaque_items
+    fn to_bits(self) -> u64 {
+        f32::to_bits(self) as u64
     }
 
-    /// The visible non-opaque rectangles in back-to-front order.
-    ///
-    /// Opaque items are only accessible after `build()`.
-    pub fn alpha_items(&self) -> &[Item] {
-        &self.alpha_items
+    // Mapping in f32 keeps the results of integer translations, flips and rotations exact.
+    fn transform_box(transform: &Transform2D<f32>, rect: &Box2D<f32>) -> Box2D<f32> {
+        map_box(transform, rect)
     }
 }
 
-#[test]
-fn basic() {
-    let mut builder = FrontToBackBuilder::new();
+impl Scalar for f64 {
+    type Area = f64;
 
-    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
-    builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, 1);
+    fn from_epsilon(eps: f32) -> Self {
+        eps as f64
+    }
 
-    assert_eq!(builder.opaque_items(), &[Item { rectangle: Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, key: 0 }]);
-    assert_eq!(builder.alpha_items(), &[
-        Item { rectangle: Box2D { min: point2(100.0, 50.0), max: point2(150.0, 100.0) }, key: 1 },
-        Item { rectangle: Box2D { min: point2(50.0, 100.0), max: point2(150.0, 150.0) }, key: 1 },
-    ]);
+    fn is_finite(self) -> bool {
+        f64::is_finite(self)
+    }
+
+    fn area<U>(rect: &euclid::Box2D<Self, U>) -> f64 {
+        rect.width() * rect.height()
+    }
+
+    fn area_to_f64(area: f64) -> f64 {
+        area
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_bits(self) -> u64 {
+        f64::to_bits(self)
+    }
 }
 
-#[test]
-fn fully_occluded_1() {
-    let mut builder = FrontToBackBuilder::new();
+macro_rules! impl_integer_scalar {
+    ($($ty:ty => $area:ty),*) => {
+        $(
+            impl Scalar for $ty {
+                type Area = $area;
 
-    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
-    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 1);
-    builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) }, false, 2);
+                fn from_epsilon(eps: f32) -> Self {
+                    eps as $ty
+                }
 
-    assert!(builder.alpha_items().is_empty());
+                fn is_finite(self) -> bool {
+                    true
+                }
+
+                // The width and height are computed in the area type since they can
+                // overflow the coordinate type.
+                fn area<U>(rect: &euclid::Box2D<Self, U>) -> $area {
+                    let width = rect.max.x as $area - rect.min.x as $area;
+                    let height = rect.max.y as $area - rect.min.y as $area;
+                    width * height
+                }
+
+                fn area_to_f64(area: $area) -> f64 {
+                    area as f64
+                }
+
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn from_f64(value: f64) -> Self {
+                    value.round() as $ty
+                }
+
+                fn to_bits(self) -> u64 {
+                    self as u64
+                }
+            }
+        )*
+    };
 }
 
-#[test]
-fn fully_occluded_2() {
-    let mut builder = FrontToBackBuilder::new();
+impl_integer_scalar!(i16 => i64, i32 => i128, i64 => i128);
 
-    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
-    builder.add(&Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) }, true, 0);
-    builder.add(&Box2D { min: point2(0.0, 100.0), max: point2(100.0, 200.0) }, true, 0);
-    builder.add(&Box2D { min: point2(100.0, 100.0), max: point2(200.0, 200.0) }, true, 0);
+/// A builder that applies occlusion culling with rectangles provided in front-to-back order.
+///
+/// It is faster than `BackToFrontBuilder`. The `E` parameter selects the tolerance of the
+/// edge comparisons, see `Epsilon`. The `D` parameter is the type of the user data carried
+/// by the items, see `add_with_data`. The `U` parameter is the unit of the coordinates of
+/// the rectangles, see `TypedFrontToBackBuilder`.
+///
+/// The `N` parameter is the number of fragments that are stored inline while splitting a
+/// rectangle, see `INLINE_FRAGMENT_CAPACITY`. A smaller value uses less stack space when
+/// rectangles rarely overlap many occluders while a larger one avoids heap allocations
+/// with dense occluders.
+///
+/// The `T` parameter is the type of the coordinates, see `ScalarFrontToBackBuilder`.
+/// Areas are returned as `T::Area`, which is wider than `T` for integer types.
+pub struct FrontToBackBuilder<
+    K = u64,
+    E = ZeroEpsilon,
+    D = (),
+    U = UnknownUnit,
+    const N: usize = INLINE_FRAGMENT_CAPACITY,
+    T: Scalar = f32,
+> {
+    opaque_items: Vec<Item<K, D, U, T>>,
+    alpha_items: Vec<Item<K, D, U, T>>,
+    occluders: OccluderSet<T>,
+    // The key of each occluder, if it comes from an added rectangle.
+    occluder_keys: Vec<Option<K>>,
+    // The visible parts of the semi-transparent rectangles and their opacity.
+    translucent: Vec<(Box2D<T>, f32)>,
+    transform: Option<AxisAlignedTransform>,
+    sort_fragments: bool,
+    item_budget: usize,
+    max_occluders: usize,
+    occluder_policy: OccluderPolicy,
+    occluder_dilation: T,
+    split_strategy: SplitStrategy,
+    trace: Option<Box<dyn std::io::Write + Send + Sync>>,
+    occupancy: Option<OccupancyGrid<T>>,
+    // The sum of the areas of the rectangles added to be rendered.
+    input_area: T::Area,
+    // The intersection of the pushed clips, for each level of the stack.
+    clip_stack: Vec<Box2D<T>>,
+    // The clip that applies to all rectangles, see `set_clip`.
+    clip: Option<Box2D<T>>,
+    // The rectangles passed to `add`, if they are retained.
+    commands: Vec<(euclid::Box2D<T, U>, bool, K)>,
+    retain_commands: bool,
+    // Whether the occluders can't change anymore, see `lock`.
+    locked: bool,
+    // The depth of the last rectangle added with `add_with_depth_check`.
+    #[cfg(debug_assertions)]
+    last_depth: f32,
+    epsilon: PhantomData<E>,
+}
 
-    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 200.0) }, false, 1);
-    builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(190.0, 190.0) }, false, 2);
+impl<K: Key> FrontToBackBuilder<K> {
+    /// Constructor.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    assert!(builder.alpha_items().is_empty());
+    /// Pre-allocating constructor.
+    pub fn with_capacity(opaque: usize, alpha: usize) -> Self {
+        Self::with_capacity_and_epsilon(opaque, alpha)
+    }
+
+    /// Constructor for a builder that retains its commands, see `set_retain_commands`.
+    pub fn retained() -> Self {
+        let mut builder = Self::new();
+        builder.set_retain_commands(true);
+
+        builder
+    }
+}
+
+/// A `FrontToBackBuilder` with coordinates in the provided unit.
+///
+/// Unlike `FrontToBackBuilder::new`, `default()` can construct builders of any unit.
+pub type TypedFrontToBackBuilder<U, K = u64> = FrontToBackBuilder<K, ZeroEpsilon, (), U>;
+
+/// A `FrontToBackBuilder` with coordinates of the provided type, see `Scalar`.
+///
+/// Like `TypedFrontToBackBuilder`, use `default()` to construct it.
+pub type ScalarFrontToBackBuilder<T, K = u64> =
+    FrontToBackBuilder<K, ZeroEpsilon, (), UnknownUnit, INLINE_FRAGMENT_CAPACITY, T>;
+
+impl<K: Key, E: Epsilon, D: Copy, U, const N: usize, T: Scalar> FrontToBackBuilder<K, E, D, U, N, T> {
+    /// Constructor for a builder with a custom `Epsilon`.
+    ///
+    /// This is the same as `default()`, which constructs builders with any epsilon, data
+    /// type, unit or inline capacity.
+    pub fn with_epsilon() -> Self {
+        Self::default()
+    }
+
+    /// Pre-allocating constructor for a builder with a custom `Epsilon`.
+    ///
+    /// Like `default()`, this also works with any data type, unit or inline capacity.
+    pub fn with_capacity_and_epsilon(opaque: usize, alpha: usize) -> Self {
+        FrontToBackBuilder {
+            opaque_items: Vec::with_capacity(opaque),
+            alpha_items: Vec::with_capacity(alpha),
+            occluders: OccluderSet { occluders: Vec::with_capacity(opaque), priorities: Vec::with_capacity(opaque) },
+            occluder_keys: Vec::with_capacity(opaque),
+            translucent: Vec::new(),
+            transform: None,
+            sort_fragments: false,
+            item_budget: usize::MAX,
+            max_occluders: usize::MAX,
+            occluder_policy: OccluderPolicy::FrontMost,
+            occluder_dilation: T::zero(),
+            split_strategy: SplitStrategy::HorizontalBands,
+            trace: None,
+            occupancy: None,
+            input_area: T::Area::zero(),
+            clip_stack: Vec::new(),
+            clip: None,
+            commands: Vec::new(),
+            retain_commands: false,
+            locked: false,
+            #[cfg(debug_assertions)]
+            last_depth: f32::NEG_INFINITY,
+            epsilon: PhantomData,
+        }
+    }
+
+    /// Constructor for a builder that clips all rectangles to the provided rectangle, in user
+    /// space, see `set_clip`.
+    ///
+    /// Returns an error if the clip is inverted, empty or not finite, since it would
+    /// silently cull all rectangles.
+    pub fn with_clip(clip: euclid::Box2D<T, U>) -> Result<Self, BuilderError> {
+        let mut builder = Self::default();
+        builder.set_clip(Some(clip))?;
+
+        Ok(builder)
+    }
+
+    /// Set the transform from the space of the provided rectangles to the space in which
+    /// the occlusion culling is performed.
+    ///
+    /// By default rectangles are split into horizontal bands in the culling space, so for example a
+    /// 90 degrees rotation produces vertical bands in the user space.
+    /// Input and output rectangles are always expressed in the user space. Note that mapping
+    /// the fragments back to the user space can introduce floating point imprecision with
+    /// transforms that aren't made of integer translations, flips and rotations.
+    pub fn set_transform(&mut self, transform: AxisAlignedTransform) {
+        self.transform = if transform == AxisAlignedTransform::identity() {
+            None
+        } else {
+            Some(transform)
+        };
+    }
+
+    /// If enabled, the items produced by each added rectangle are sorted top-to-bottom then
+    /// left-to-right.
+    ///
+    /// By default they are stored in the order the splitting algorithm produces them, which
+    /// is deterministic but not intuitive. Sorting makes snapshots easier to read and doesn't
+    /// affect the order between items of different rectangles.
+    pub fn set_sort_fragments(&mut self, enabled: bool) {
+        self.sort_fragments = enabled;
+    }
+
+    /// Select how rectangles are split around the occluders, in the culling space.
+    ///
+    /// The default is `SplitStrategy::HorizontalBands`.
+    pub fn set_split_strategy(&mut self, strategy: SplitStrategy) {
+        self.split_strategy = strategy;
+    }
+
+    /// Add a rectangle, potentially splitting it and discarding the occluded parts if any.
+    ///
+    /// Returns true the rectangle is at least partially visible.
+    ///
+    /// Rectangles with a zero or negative area are discarded and are never visible, this
+    /// applies to all of the methods that add or test rectangles. So are rectangles with
+    /// non-finite coordinates, which also trigger an assertion in debug builds since they
+    /// usually come from a bug upstream.
+    ///
+    /// When several identical opaque rectangles are added, the first one (the front-most)
+    /// is kept and the others are discarded.
+    ///
+    /// Besides the growth of the output lists, this only allocates if the rectangle is
+    /// split into more than `N` parts at any point.
+    pub fn add(&mut self, rect: &euclid::Box2D<T, U>, is_opaque: bool, key: K) -> bool
+    where
+        D: Default,
+    {
+        let kind = if is_opaque { ItemKind::Opaque } else { ItemKind::Alpha };
+        // Rejected rectangles aren't recorded so that `rebuild` produces the same results.
+        if self.rejects(kind) {
+            return false;
+        }
+        if self.retain_commands {
+            self.commands.push((*rect, is_opaque, key));
+        }
+        self.add_with_kind(rect, kind, key)
+    }
+
+    /// If true, the rectangles passed to `add` are recorded so that the results can be
+    /// computed again with `rebuild`, for example after editing the commands.
+    ///
+    /// Only `add`, `add_detailed`, `add_with_fragments`, `add_with_depth_check` and
+    /// `extend` are recorded, since the commands can't represent what the other methods
+    /// do. The depth of `add_with_depth_check` and the clip stack aren't recorded either.
+    /// Defaults to false.
+    pub fn set_retain_commands(&mut self, retain: bool) {
+        self.retain_commands = retain;
+    }
+
+    /// The rectangles recorded since the last `clear`, in front-to-back order.
+    pub fn commands(&self) -> &[(euclid::Box2D<T, U>, bool, K)] {
+        &self.commands
+    }
+
+    /// Mutable access to the recorded rectangles, to edit them before a `rebuild`.
+    pub fn commands_mut(&mut self) -> &mut Vec<(euclid::Box2D<T, U>, bool, K)> {
+        &mut self.commands
+    }
+
+    /// Same as `clear` followed by adding the recorded rectangles again with `add`.
+    ///
+    /// Returns the number of rectangles that are at least partially visible.
+    pub fn rebuild(&mut self) -> usize
+    where
+        D: Default,
+    {
+        let commands = std::mem::take(&mut self.commands);
+        self.clear();
+        self.commands = Vec::with_capacity(commands.len());
+
+        self.extend(commands)
+    }
+
+    /// Same as `add` but splits the rectangle in the provided buffer, which is cleared first.
+    ///
+    /// Unlike `add`, this never allocates besides the growth of the output lists and of
+    /// the buffer, which is kept for the next calls.
+    pub fn add_with_scratch(
+        &mut self,
+        rect: &euclid::Box2D<T, U>,
+        is_opaque: bool,
+        key: K,
+        scratch: &mut FragmentScratch<T>,
+    ) -> bool
+    where
+        D: Default,
+    {
+        let fragments = &mut scratch.fragments;
+        fragments.clear();
+        let kind = if is_opaque { ItemKind::Opaque } else { ItemKind::Alpha };
+        if self.rejects(kind) {
+            return false;
+        }
+        let rect = &rect.to_untyped();
+        let clipped = match self.clip_rect(rect) {
+            Some(clipped) => clipped,
+            None => return false,
+        };
+        self.cull(&clipped, fragments);
+        self.push_fragments(rect, fragments, kind, key, D::default());
+
+        !fragments.is_empty()
+    }
+
+    /// Same as `add` but also returns the number of items the rectangle was split into.
+    ///
+    /// This is useful to find the rectangles that are the most expensive to cull.
+    pub fn add_detailed(&mut self, rect: &euclid::Box2D<T, U>, is_opaque: bool, key: K) -> AddResult
+    where
+        D: Default,
+    {
+        let count = self.opaque_items.len() + self.alpha_items.len();
+        let visible = self.add(rect, is_opaque, key);
+
+        AddResult {
+            visible,
+            fragment_count: self.opaque_items.len() + self.alpha_items.len() - count,
+        }
+    }
+
+    /// Same as `add` but also appends the visible parts of the rectangle to `output`, in
+    /// user space.
+    ///
+    /// They are the rectangles of the items stored for it, in the same order.
+    pub fn add_with_fragments(
+        &mut self,
+        rect: &euclid::Box2D<T, U>,
+        is_opaque: bool,
+        key: K,
+        output: &mut Vec<euclid::Box2D<T, U>>,
+    ) -> bool
+    where
+        D: Default,
+    {
+        let start = if is_opaque { self.opaque_items.len() } else { self.alpha_items.len() };
+        let visible = self.add(rect, is_opaque, key);
+        let list = if is_opaque { &self.opaque_items } else { &self.alpha_items };
+        output.extend(list[start..].iter().map(|item| item.rectangle));
+
+        visible
+    }
+
+    /// Add several rectangles in front-to-back order with `add`.
+    ///
+    /// Returns the number of rectangles that are at least partially visible.
+    pub fn extend<I>(&mut self, rects: I) -> usize
+    where
+        I: IntoIterator<Item = (euclid::Box2D<T, U>, bool, K)>,
+        D: Default,
+    {
+        let mut count = 0;
+        for (rect, is_opaque, key) in rects {
+            if self.add(&rect, is_opaque, key) {
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    /// Push a clip rectangle, in user space.
+    ///
+    /// Until it is popped, the rectangles passed to `add`, `add_with_kind`, `try_add`,
+    /// `add_with_policy` and `add_with_opacity` are intersected with it and with the
+    /// previously pushed clips before being culled. Items still refer to the unclipped
+    /// rectangle as their original. The methods that test rectangles without adding them,
+    /// such as `test` and `visible_fraction`, also consider the parts outside of the clips
+    /// as not visible.
+    pub fn push_clip(&mut self, clip: &euclid::Box2D<T, U>) {
+        let clip = &clip.to_untyped();
+        let clip = match self.clip_stack.last() {
+            Some(current) => current.intersection(clip).unwrap_or_else(Box2D::zero),
+            None => *clip,
+        };
+        self.clip_stack.push(clip);
+    }
+
+    /// Set a clip rectangle, in user space, that applies to all rectangles in addition to
+    /// the clip stack.
+    ///
+    /// This is typically the viewport. Like the pushed clips, it applies to the rectangles
+    /// passed to `add` and the methods listed in `push_clip`, so that the parts outside of
+    /// it are neither stored nor become occluders, and to the methods that test rectangles.
+    /// Unlike them, it isn't reset by `clear`.
+    /// Passing `None` removes the clip, which is the default.
+    ///
+    /// Returns an error and keeps the previous clip if the clip is inverted, empty or not
+    /// finite, since it would silently cull all rectangles.
+    pub fn set_clip(&mut self, clip: Option<euclid::Box2D<T, U>>) -> Result<(), BuilderError> {
+        let clip = clip.map(|clip| clip.to_untyped());
+        if let Some(clip) = &clip {
+            check_clip(clip)?;
+        }
+        self.clip = clip;
+
+        Ok(())
+    }
+
+    /// Pop the most recently pushed clip rectangle.
+    pub fn pop_clip(&mut self) {
+        debug_assert!(!self.clip_stack.is_empty());
+        self.clip_stack.pop();
+    }
+
+    // Intersect the rect with the current clips, returns None if nothing is left.
+    fn clip_rect(&self, rect: &Box2D<T>) -> Option<Box2D<T>> {
+        if is_degenerate(rect) {
+            return None;
+        }
+        let rect = match &self.clip {
+            Some(clip) => clip.intersection(rect)?,
+            None => *rect,
+        };
+        match self.clip_stack.last() {
+            Some(clip) => clip.intersection(&rect),
+            None => Some(rect),
+        }
+    }
+
+    /// Set the maximum number of opaque and alpha items that `try_add` may store.
+    ///
+    /// This protects against scenes that fragment into a very large number of rectangles.
+    /// There is no budget by default.
+    pub fn set_item_budget(&mut self, budget: usize) {
+        self.item_budget = budget;
+    }
+
+    /// Set the maximum number of occluders that incoming rectangles are tested against.
+    ///
+    /// Capping the occluders bounds the cost of `add` in scenes with many opaque rectangles,
+    /// at the expense of culling less. There is no cap by default.
+    ///
+    /// Once the cap is reached, opaque items can overlap the ones of the opaque rectangles
+    /// that were capped or evicted, which requires rendering the opaque pass with a depth
+    /// test. See `OccluderPolicy`.
+    pub fn set_max_occluders(&mut self, max: usize) {
+        self.max_occluders = max;
+    }
+
+    /// Maintain a coarse grid of the cells of the viewport that contain occluders, so that
+    /// rectangles that only touch empty cells skip testing the occluders.
+    ///
+    /// This speeds up scenes where the occluders are clustered in a small part of the
+    /// viewport. Rectangles outside of the viewport are still culled correctly but don't
+    /// benefit from the grid. Passing `None` disables the grid, which is the default.
+    pub fn set_occupancy_grid(&mut self, viewport: Option<euclid::Box2D<T, U>>) {
+        self.occupancy = viewport.map(|viewport| {
+            let mut grid = OccupancyGrid { viewport: viewport.to_untyped(), mask: 0 };
+            for occluder in &self.occluders.occluders {
+                grid.mask |= grid.cells(occluder);
+            }
+            grid
+        });
+    }
+
+    /// Freeze the occluders, for scenes where the occluders are known up front and only
+    /// transient content is culled against them.
+    ///
+    /// Adding opaque rectangles or occluders afterwards is a logic error: it panics in debug
+    /// builds, and in release builds the rectangle is not stored and reported as not
+    /// visible. Alpha and `ItemKind::OpaqueNonOccluding` rectangles can still be added.
+    ///
+    /// The only precomputed structure is the 8x8 occupancy grid: if none was set, one
+    /// covering the bounds of the occluders is computed once. `clear` unlocks the occluders
+    /// but keeps the grid.
+    pub fn lock(&mut self) {
+        if self.occupancy.is_none() {
+            let bounds = self.occluders.occluders.iter().cloned().reduce(|a, b| a.union(&b));
+            if let Some(bounds) = bounds {
+                self.set_occupancy_grid(Some(euclid::Box2D::from_untyped(&bounds)));
+            }
+        }
+        self.locked = true;
+    }
+
+    /// Remove the opaque items of the provided key and the occluders they produced.
+    ///
+    /// This does not restore the parts of the rectangles added afterwards that were
+    /// culled by these items: they stay discarded, so this is only exact for the rectangles
+    /// that are added after the removal. When the occluders are locked, they are kept and
+    /// only the items are removed. The occupancy grid keeps the cells of the removed
+    /// occluders marked, which is conservative.
+    ///
+    /// Returns the number of removed items.
+    pub fn remove_opaque(&mut self, key: K) -> usize
+    where
+        K: PartialEq,
+    {
+        let count = self.opaque_items.len();
+        self.opaque_items.retain(|item| item.key != key);
+
+        if !self.locked {
+            let occluders = &mut self.occluders;
+            let mut kept = 0;
+            for i in 0..self.occluder_keys.len() {
+                if self.occluder_keys[i] != Some(key) {
+                    self.occluder_keys.swap(kept, i);
+                    occluders.occluders.swap(kept, i);
+                    occluders.priorities.swap(kept, i);
+                    kept += 1;
+                }
+            }
+            self.occluder_keys.truncate(kept);
+            occluders.occluders.truncate(kept);
+            occluders.priorities.truncate(kept);
+        }
+
+        count - self.opaque_items.len()
+    }
+
+    /// Whether `lock` was called since the last `clear`.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Set which occluders are kept when their number is capped.
+    ///
+    /// The default is `OccluderPolicy::FrontMost`.
+    pub fn set_occluder_policy(&mut self, policy: OccluderPolicy) {
+        self.occluder_policy = policy;
+    }
+
+    /// Treat occluders as if they were larger by `margin` on each side when culling, in
+    /// culling space.
+    ///
+    /// This discards slivers of rectangles that are only visible near the edges of the
+    /// occluders, at the cost of possibly dropping thin visible parts. It is useful when the
+    /// margin is within the anti-aliasing tolerance. The stored opaque items are not affected.
+    ///
+    /// Defaults to zero.
+    pub fn set_occluder_dilation(&mut self, margin: T) {
+        self.occluder_dilation = margin;
+    }
+
+    /// Write a line to the provided output each time a rectangle is added, with the number
+    /// of occluders it intersects and the resulting visible parts.
+    ///
+    /// Occluders are counted the way they are applied, in culling space and including the
+    /// occluder dilation. The output must be `Sync` so that the builder stays `Sync`.
+    ///
+    /// This is intended for debugging. Passing `None` disables tracing, which is the default
+    /// and has no cost beyond checking that it is disabled.
+    pub fn set_trace_output(&mut self, output: Option<Box<dyn std::io::Write + Send + Sync>>) {
+        self.trace = output;
+    }
+
+    /// Same as `add` but doesn't store anything and returns an error if the item budget
+    /// would be exceeded.
+    ///
+    /// When that happens, the caller can fall back to rendering without occlusion culling.
+    pub fn try_add(&mut self, rect: &euclid::Box2D<T, U>, is_opaque: bool, key: K) -> Result<bool, CapacityExceeded>
+    where
+        D: Default,
+    {
+        let kind = if is_opaque { ItemKind::Opaque } else { ItemKind::Alpha };
+        if self.rejects(kind) {
+            return Ok(false);
+        }
+        let rect = &rect.to_untyped();
+        let clipped = match self.clip_rect(rect) {
+            Some(clipped) => clipped,
+            None => return Ok(false),
+        };
+        let mut fragments: SmallVec<[TaggedFragment<T>; N]> = SmallVec::new();
+        self.cull(&clipped, &mut fragments);
+
+        let count = self.opaque_items.len() + self.alpha_items.len();
+        if fragments.len() > self.item_budget.saturating_sub(count) {
+            return Err(CapacityExceeded);
+        }
+
+        self.push_fragments(rect, &fragments, kind, key, D::default());
+
+        Ok(!fragments.is_empty())
+    }
+
+    /// Same as `add` but the rectangle is never split: it is discarded if it is fully
+    /// occluded and stored whole otherwise.
+    ///
+    /// This is intended for rectangles that are rendered with a non-axis-aligned
+    /// transformation, for which splitting could cause artifacts. A partially visible
+    /// opaque rectangle overlaps the opaque items in front of it, so the opaque pass must
+    /// then be rendered in front-to-back order with a depth test.
+    ///
+    /// Returns true the rectangle is at least partially visible.
+    pub fn add_unsplittable(&mut self, rect: &euclid::Box2D<T, U>, is_opaque: bool, key: K) -> bool
+    where
+        D: Default,
+    {
+        let kind = if is_opaque { ItemKind::Opaque } else { ItemKind::Alpha };
+        if self.rejects(kind) {
+            return false;
+        }
+        let rect = &rect.to_untyped();
+        let clipped = match self.clip_rect(rect) {
+            Some(clipped) => clipped,
+            None => return false,
+        };
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        self.cull(&clipped, &mut fragments);
+        let visible = !fragments.is_empty();
+
+        // Fully occluded rectangles go through `push_fragments` as well so that they are
+        // accounted for the same way as in the split path. Only the clipped part becomes
+        // an occluder since the rest isn't drawn, the stored item is widened afterwards.
+        let fragment = TaggedFragment {
+            rect: to_cull_space(&self.transform, &clipped),
+            origin: FragmentOrigin::Unsplit,
+        };
+        let fragments: &[TaggedFragment<T>] = if visible { &[fragment] } else { &[] };
+        self.push_fragments(rect, fragments, kind, key, D::default());
+        if visible {
+            let list = if is_opaque { &mut self.opaque_items } else { &mut self.alpha_items };
+            if let Some(item) = list.last_mut() {
+                item.rectangle = euclid::Box2D::from_untyped(rect);
+            }
+        }
+
+        visible
+    }
+
+    /// Add several rectangles in front-to-back order with `add_unsplittable`, writing
+    /// whether each of them is visible into the corresponding element of `visible`.
+    ///
+    /// Returns the number of visible rectangles.
+    pub fn add_unsplittable_many(&mut self, rects: &[(euclid::Box2D<T, U>, bool, K)], visible: &mut [bool]) -> usize
+    where
+        D: Default,
+    {
+        assert_eq!(rects.len(), visible.len());
+
+        let mut count = 0;
+        for (&(rect, is_opaque, key), visible) in rects.iter().zip(visible.iter_mut()) {
+            *visible = self.add_unsplittable(&rect, is_opaque, key);
+            if *visible {
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    /// Same as `add` but asserts in debug builds that the provided depths don't decrease,
+    /// which catches rectangles that aren't added in front-to-back order.
+    ///
+    /// Larger depths are further away. The depth is ignored in release builds and when it
+    /// is `None`.
+    pub fn add_with_depth_check(&mut self, rect: &euclid::Box2D<T, U>, is_opaque: bool, key: K, depth: Option<f32>) -> bool
+    where
+        D: Default,
+    {
+        #[cfg(debug_assertions)]
+        if let Some(depth) = depth {
+            assert!(
+                depth >= self.last_depth,
+                "rectangles must be added in front-to-back order (depth {} after {})",
+                depth,
+                self.last_depth,
+            );
+            self.last_depth = depth;
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = depth;
+
+        self.add(rect, is_opaque, key)
+    }
+
+    /// Same as `add` but also allows opaque rectangles that don't act as occluders and
+    /// occluders that aren't rendered.
+    pub fn add_with_kind(&mut self, rect: &euclid::Box2D<T, U>, kind: ItemKind, key: K) -> bool
+    where
+        D: Default,
+    {
+        self.add_with_kind_and_data(rect, kind, key, D::default())
+    }
+
+    /// Same as `add` but also stores user data that is copied to each of the visible
+    /// parts of the rectangle.
+    ///
+    /// The other methods store `D::default()`.
+    pub fn add_with_data(&mut self, rect: &euclid::Box2D<T, U>, is_opaque: bool, key: K, data: D) -> bool {
+        let kind = if is_opaque { ItemKind::Opaque } else { ItemKind::Alpha };
+        self.add_with_kind_and_data(rect, kind, key, data)
+    }
+
+    fn add_with_kind_and_data(&mut self, rect: &euclid::Box2D<T, U>, kind: ItemKind, key: K, data: D) -> bool {
+        if self.rejects(kind) {
+            return false;
+        }
+        let rect = &rect.to_untyped();
+        let clipped = match self.clip_rect(rect) {
+            Some(clipped) => clipped,
+            None => return false,
+        };
+        let mut fragments: SmallVec<[TaggedFragment<T>; N]> = SmallVec::new();
+        self.cull(&clipped, &mut fragments);
+        self.push_fragments(rect, &fragments, kind, key, data);
+
+        !fragments.is_empty()
+    }
+
+    /// Add a rectangle with a known opacity between 0.0 and 1.0.
+    ///
+    /// Fully opaque rectangles are added as opaque items. Fully transparent ones are
+    /// ignored. Others are added as alpha items and their opacity is taken into account by
+    /// `effective_visible_fraction`.
+    ///
+    /// Returns true the rectangle is at least partially visible.
+    pub fn add_with_opacity(&mut self, rect: &euclid::Box2D<T, U>, opacity: f32, key: K) -> bool
+    where
+        D: Default,
+    {
+        if opacity <= 0.0 {
+            return false;
+        }
+        if opacity >= 1.0 {
+            return self.add_with_kind(rect, ItemKind::Opaque, key);
+        }
+
+        let count = self.alpha_items.len();
+        let visible = self.add_with_kind(rect, ItemKind::Alpha, key);
+        for item in &self.alpha_items[count..] {
+            self.translucent.push((item.rectangle.to_untyped(), opacity));
+        }
+
+        visible
+    }
+
+    /// Returns the fraction of the provided rect that shows through, between 0.0 and 1.0,
+    /// without adding it.
+    ///
+    /// Unlike the binary visibility used for culling, the parts of the rect that are behind
+    /// rectangles added with `add_with_opacity` are weighted by the transparency of each of
+    /// them. This is intended for cost estimations rather than culling. Alpha items added
+    /// without an opacity are considered fully transparent.
+    pub fn effective_visible_fraction(&self, rect: &euclid::Box2D<T, U>) -> f32 {
+        let rect = &rect.to_untyped();
+        let total = area_f64(rect);
+        if total <= 0.0 {
+            return 0.0;
+        }
+
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        self.cull_clipped(rect, &mut fragments);
+
+        let transform = &self.transform;
+        let mut weighted: Vec<(Box2D<T>, f32)> = fragments
+            .iter()
+            .map(|fragment| (from_cull_space(transform, fragment), 1.0))
+            .collect();
+
+        let mut outside: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        for &(translucent, opacity) in &self.translucent {
+            let count = weighted.len();
+            for i in 0..count {
+                let (fragment, weight) = weighted[i];
+                let inside = match fragment.intersection(&translucent) {
+                    Some(inside) => inside,
+                    None => continue,
+                };
+
+                outside.clear();
+                outside.push(fragment);
+                apply_occluder(&translucent, &mut outside);
+
+                weighted[i] = (inside, weight * (1.0 - opacity));
+                weighted.extend(outside.iter().map(|r| (*r, weight)));
+            }
+        }
+
+        let visible: f64 = weighted.iter().map(|(r, weight)| area_f64(r) * *weight as f64).sum();
+
+        (visible / total) as f32
+    }
+
+    /// Add a rectangle, letting a policy decide how to store it depending on its visibility.
+    ///
+    /// The policy is called with the visible fraction of the rectangle, between 0.0 and 1.0.
+    /// For example a partially hidden opaque rectangle can be added as alpha to avoid
+    /// splitting the rectangles behind it with a small occluder.
+    ///
+    /// Returns true the rectangle is at least partially visible.
+    pub fn add_with_policy(
+        &mut self,
+        rect: &euclid::Box2D<T, U>,
+        key: K,
+        policy: impl FnOnce(f32) -> ItemKind,
+    ) -> bool
+    where
+        D: Default,
+    {
+        let rect = &rect.to_untyped();
+        let clipped = match self.clip_rect(rect) {
+            Some(clipped) => clipped,
+            None => return false,
+        };
+        let mut fragments: SmallVec<[TaggedFragment<T>; N]> = SmallVec::new();
+        self.cull(&clipped, &mut fragments);
+
+        let visible: f64 = fragments.iter().map(|fragment| area_f64(&fragment.rect)).sum();
+        let total = area_f64(&to_cull_space(&self.transform, &clipped));
+        let coverage = if total > 0.0 { (visible / total) as f32 } else { 0.0 };
+
+        let kind = policy(coverage);
+        if self.rejects(kind) {
+            return false;
+        }
+        self.push_fragments(rect, &fragments, kind, key, D::default());
+
+        !fragments.is_empty()
+    }
+
+    /// Cull rectangles coming from a different source than the occluders.
+    ///
+    /// The occluders are added to the occluder set without being stored in the opaque
+    /// list, then each of the items is added as alpha. Both are considered to be behind
+    /// the previously added rectangles.
+    ///
+    /// Returns the number of items that are at least partially visible.
+    pub fn cull_against(
+        &mut self,
+        occluders: impl Iterator<Item = euclid::Box2D<T, U>>,
+        items: impl Iterator<Item = (euclid::Box2D<T, U>, K)>,
+    ) -> usize
+    where
+        D: Default,
+    {
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        for occluder in occluders {
+            fragments.clear();
+            self.cull(&occluder.to_untyped(), &mut fragments);
+            for fragment in &fragments {
+                self.add_occluder(&from_cull_space(&self.transform, fragment), None);
+            }
+        }
+
+        let mut visible = 0;
+        for (rect, key) in items {
+            if self.add_with_kind(&rect, ItemKind::Alpha, key) {
+                visible += 1;
+            }
+        }
+
+        visible
+    }
+
+    /// Combine the results of a builder that was used to cull a separate part of the scene.
+    ///
+    /// The opaque items of each builder occlude the alpha items of the other one, which
+    /// assumes that wherever they overlap, the opaque content of one part is in front of the
+    /// alpha content of the other. Where opaque items of both builders overlap, the ones
+    /// from `self` are kept so that the opaque items still don't overlap.
+    ///
+    /// The alpha items of `other` are placed after the ones of `self`, as if they were behind.
+    pub fn merge(&mut self, other: &FrontToBackBuilder<K, E, D, U, N, T>) {
+        let self_alpha = std::mem::take(&mut self.alpha_items);
+        other.recull_items(&self_alpha, &mut self.alpha_items);
+
+        let mut other_opaque = Vec::new();
+        let mut other_alpha = Vec::new();
+        self.recull_items(&other.opaque_items, &mut other_opaque);
+        self.recull_items(&other.alpha_items, &mut other_alpha);
+
+        let mut occluders: Vec<(Box2D<T>, Option<K>)> = Vec::new();
+        let mut translucent: Vec<(Box2D<T>, f32)> = Vec::new();
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        for (occluder, &key) in other.occluders.occluders.iter().zip(&other.occluder_keys) {
+            fragments.clear();
+            self.cull(occluder, &mut fragments);
+            occluders.extend(fragments.iter().map(|f| (from_cull_space(&self.transform, f), key)));
+        }
+        for &(rect, opacity) in &other.translucent {
+            fragments.clear();
+            self.cull(&rect, &mut fragments);
+            translucent.extend(fragments.iter().map(|f| (from_cull_space(&self.transform, f), opacity)));
+        }
+
+        self.opaque_items.extend(other_opaque);
+        self.alpha_items.extend(other_alpha);
+        self.translucent.extend(translucent);
+        self.input_area = self.input_area + other.input_area;
+        for (occluder, key) in &occluders {
+            self.add_occluder(occluder, *key);
+        }
+    }
+
+    // Append the visible parts of already culled items to the output, keeping their
+    // original rectangle and key.
+    fn recull_items(&self, items: &[Item<K, D, U, T>], output: &mut Vec<Item<K, D, U, T>>) {
+        let transform = self.transform;
+        let mut fragments: SmallVec<[TaggedFragment<T>; N]> = SmallVec::new();
+        for item in items {
+            fragments.clear();
+            self.cull(&item.rectangle.to_untyped(), &mut fragments);
+            for fragment in &fragments {
+                let origin = match (fragment.origin, transform) {
+                    (FragmentOrigin::Unsplit, _) => item.origin,
+                    (origin, Some(t)) => t.inverse_transform_origin(origin),
+                    (origin, None) => origin,
+                };
+                output.push(Item {
+                    rectangle: euclid::Box2D::from_untyped(&from_cull_space(&transform, &fragment.rect)),
+                    original: item.original,
+                    origin,
+                    key: item.key,
+                    data: item.data,
+                });
+            }
+        }
+    }
+
+    fn write_trace(
+        &self,
+        output: &mut dyn std::io::Write,
+        rect: &Box2D<T>,
+        fragments: &[TaggedFragment<T>],
+        kind: ItemKind,
+    ) -> std::io::Result<()> {
+        let transform = &self.transform;
+        let occluder_count = self.overlapping_occluder_count(rect);
+        writeln!(
+            output,
+            "add {:?} {:?}: {} occluders, {} fragments",
+            kind,
+            rect,
+            occluder_count,
+            fragments.len(),
+        )?;
+        for fragment in fragments {
+            let visible = from_cull_space(transform, &fragment.rect);
+            writeln!(output, "    {:?} {:?}", fragment.origin, visible)?;
+        }
+
+        Ok(())
+    }
+
+    // Whether the kind of rectangle can't be added because the occluders are locked.
+    fn rejects(&self, kind: ItemKind) -> bool {
+        let rejected = self.locked && matches!(kind, ItemKind::Opaque | ItemKind::OccluderOnly);
+        debug_assert!(!rejected, "opaque rectangles can't be added after lock()");
+
+        rejected
+    }
+
+    // Add an occluder in user space, respecting the occluder cap.
+    fn add_occluder(&mut self, rect: &Box2D<T>, key: Option<K>) {
+        if self.locked {
+            return;
+        }
+
+        // Cells are only ever added, an evicted occluder leaves its cells marked which
+        // is conservative.
+        if let Some(grid) = &mut self.occupancy {
+            grid.mask |= grid.cells(rect);
+        }
+
+        if self.occluders.occluders.len() < self.max_occluders {
+            self.occluders.add(rect);
+            self.occluder_keys.push(key);
+            return;
+        }
+
+        let occluders = &mut self.occluders.occluders;
+        if self.occluder_policy == OccluderPolicy::LargestArea {
+            let smallest = occluders
+                .iter()
+                .enumerate()
+                .min_by(|a, b| area(a.1).partial_cmp(&area(b.1)).unwrap_or(Ordering::Equal));
+            if let Some((idx, smallest)) = smallest {
+                if area(rect) > area(smallest) {
+                    occluders[idx] = *rect;
+                    self.occluder_keys[idx] = key;
+                }
+            }
+        }
+    }
+
+    fn push_fragments(
+        &mut self,
+        rect: &Box2D<T>,
+        fragments: &[TaggedFragment<T>],
+        kind: ItemKind,
+        key: K,
+        data: D,
+    ) {
+        let transform = self.transform;
+        if let Some(mut output) = self.trace.take() {
+            // Errors are ignored, tracing is best-effort.
+            let _ = self.write_trace(&mut *output, rect, fragments, kind);
+            self.trace = Some(output);
+        }
+
+        if kind == ItemKind::Opaque || kind == ItemKind::OccluderOnly {
+            for fragment in fragments {
+                self.add_occluder(&from_cull_space(&transform, &fragment.rect), Some(key));
+            }
+        }
+
+        let list = match kind {
+            ItemKind::Opaque | ItemKind::OpaqueNonOccluding => &mut self.opaque_items,
+            ItemKind::Alpha => &mut self.alpha_items,
+            ItemKind::OccluderOnly => return,
+        };
+        self.input_area = self.input_area + area(rect);
+
+        // Pushing a single item doesn't benefit from reserving, let the vector
+        // grow with its usual strategy.
+        if fragments.len() > 1 {
+            list.reserve(fragments.len());
+        }
+
+        let start = list.len();
+        for fragment in fragments {
+            let origin = match transform {
+                Some(t) => t.inverse_transform_origin(fragment.origin),
+                None => fragment.origin,
+            };
+            list.push(Item {
+                rectangle: euclid::Box2D::from_untyped(&from_cull_space(&transform, &fragment.rect)),
+                original: euclid::Box2D::from_untyped(rect),
+                origin,
+                key,
+                data,
+            });
+        }
+
+        if self.sort_fragments {
+            sort_spatially(&mut list[start..]);
+        }
+    }
+
+    /// Returns true if the provided rect is at least partially visible, without adding it.
+    pub fn test(&self, rect: &euclid::Box2D<T, U>) -> bool {
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        self.cull_clipped(&rect.to_untyped(), &mut fragments);
+
+        !fragments.is_empty()
+    }
+
+    /// Returns true if a rectangle moving linearly from `start` to `end` is at least partially
+    /// visible at some point, without adding it.
+    ///
+    /// This conservatively tests the bounding box of both positions, which contains the
+    /// whole swept region but can also contain occluded corners that the rectangle never
+    /// goes through when moving diagonally.
+    pub fn test_swept(&self, start: euclid::Box2D<T, U>, end: euclid::Box2D<T, U>) -> bool {
+        self.test(&start.union(&end))
+    }
+
+    /// Returns true if the visible area of the provided rect is at least `min_area`, without
+    /// adding it.
+    ///
+    /// This is a stricter version of `test` for rectangles that aren't worth rendering when
+    /// only a small portion of them is visible.
+    pub fn test_min_area(&self, rect: &euclid::Box2D<T, U>, min_area: T::Area) -> bool {
+        self.visible_area(&rect.to_untyped()) >= min_area
+    }
+
+    /// Returns the fraction of the area of the provided rect that is visible, between 0.0
+    /// and 1.0, without adding it.
+    ///
+    /// Unlike `effective_visible_fraction`, alpha items don't hide anything. Rectangles
+    /// with a zero or negative area have no visible fraction.
+    pub fn visible_fraction(&self, rect: &euclid::Box2D<T, U>) -> f32 {
+        let rect = &rect.to_untyped();
+        if rect.is_empty() {
+            return 0.0;
+        }
+
+        (T::area_to_f64(self.visible_area(rect)) / area_f64(rect)).min(1.0) as f32
+    }
+
+    /// Test the visibility of many rectangles at once, without adding them.
+    ///
+    /// The `out` vector is cleared and filled with one boolean per rectangle, in
+    /// the same order as `rects`.
+    pub fn test_batch(&self, rects: &[euclid::Box2D<T, U>], out: &mut Vec<bool>) {
+        out.clear();
+        out.reserve(rects.len());
+
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        for rect in rects {
+            fragments.clear();
+            self.cull_clipped(&rect.to_untyped(), &mut fragments);
+            out.push(!fragments.is_empty());
+        }
+    }
+
+    /// Returns the number of occluders that overlap the provided rect.
+    ///
+    /// Occluders are compared with the rect the same way as when it is added, including
+    /// the clips, the occluder dilation and the epsilon. This is the number of occluders
+    /// the rectangle would be split against if it was added, which is a good indicator of
+    /// the cost of adding it.
+    pub fn candidate_occluder_count(&self, rect: &euclid::Box2D<T, U>) -> usize {
+        self.overlapping_occluder_count(&rect.to_untyped())
+    }
+
+    // The number of occluders that overlap the clipped rect in culling space, see `cull`.
+    fn overlapping_occluder_count(&self, rect: &Box2D<T>) -> usize {
+        let clipped = match self.clip_rect(rect) {
+            Some(clipped) => clipped,
+            None => return 0,
+        };
+        let transform = &self.transform;
+        let margin = self.occluder_dilation;
+        let clipped = to_cull_space(transform, &clipped);
+        self.occluders.occluders.iter().filter(|occluder| {
+            let occluder = to_cull_space(transform, occluder).inflate(margin, margin);
+            !occluder.is_empty() && overlaps::<E, _>(&occluder, &clipped)
+        }).count()
+    }
+
+    /// The number of items in the opaque list.
+    pub fn stored_opaque_count(&self) -> usize {
+        self.opaque_items.len()
+    }
+
+    /// The number of rectangles that incoming rectangles are tested against.
+    ///
+    /// This is what drives the cost of `add` and `test`. It can differ from
+    /// `stored_opaque_count` when some opaque items don't occlude.
+    pub fn active_occluder_count(&self) -> usize {
+        self.occluders.occluders.len()
+    }
+
+    /// The occluders that incoming rectangles are tested against, in user space.
+    ///
+    /// The set can be cloned and kept around, for example to test rectangles against the
+    /// occluders of a previous frame. Its coordinates don't carry the unit of the builder.
+    pub fn occluder_set(&self) -> &OccluderSet<T> {
+        &self.occluders
+    }
+
+    /// Returns the area of the parts of the provided rect that are hidden by opaque items.
+    ///
+    /// This is zero if the rect is fully visible and the area of the rect if it is fully
+    /// occluded. The parts outside of the clip, see `set_clip`, aren't counted.
+    pub fn occluded_area(&self, rect: &euclid::Box2D<T, U>) -> T::Area {
+        let rect = &rect.to_untyped();
+        let total = match self.clip_rect(rect) {
+            Some(clipped) => area(&clipped),
+            None => return T::Area::zero(),
+        };
+        let visible = self.visible_area(rect);
+        if visible < total { total - visible } else { T::Area::zero() }
+    }
+
+    /// Returns the fraction of the region that is covered by the visible items of the
+    /// provided key.
+    ///
+    /// Both opaque and alpha items are taken into account. Alpha items may be partially
+    /// covered by other alpha items in front of them, which this does not account for.
+    pub fn visible_coverage_of_key_in_region(&self, key: K, region: &euclid::Box2D<T, U>) -> f32
+    where
+        K: PartialEq,
+    {
+        let region_area = area_f64(region);
+        if region_area <= 0.0 {
+            return 0.0;
+        }
+
+        let covered: f64 = self
+            .opaque_items
+            .iter()
+            .chain(self.alpha_items.iter())
+            .filter(|item| item.key == key)
+            .filter_map(|item| item.rectangle.intersection(region))
+            .map(|rect| area_f64(&rect))
+            .sum();
+
+        (covered / region_area) as f32
+    }
+
+    /// Append a set of non-overlapping rectangles that cover the union of the alpha items
+    /// to the output vector.
+    ///
+    /// Each alpha item is split against the rectangles emitted for the previous ones, so the
+    /// result is not necessarily the smallest possible decomposition.
+    pub fn alpha_union(&self, output: &mut Vec<euclid::Box2D<T, U>>) {
+        let start = output.len();
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        for item in &self.alpha_items {
+            fragments.clear();
+            let union = output[start..].iter().map(|r| r.to_untyped());
+            cull_fragments(union, &item.rectangle.to_untyped(), &mut fragments);
+            output.extend(fragments.iter().map(euclid::Box2D::from_untyped));
+        }
+    }
+
+    /// Returns true if the point is covered by an opaque item.
+    ///
+    /// Points on the maximum edges of an item are not covered by it, see `Box2D::contains`.
+    pub fn is_point_occluded(&self, point: euclid::Point2D<T, U>) -> bool {
+        self.occluder_at(point).is_some()
+    }
+
+    /// Returns the front-most opaque item that covers the point, if any.
+    ///
+    /// Opaque items only overlap if some of them were added with `add_unsplittable` or
+    /// `ItemKind::OpaqueNonOccluding`, or once the occluders are capped, see
+    /// `set_max_occluders`. Otherwise there is at most one item covering the point.
+    pub fn occluder_at(&self, point: euclid::Point2D<T, U>) -> Option<&Item<K, D, U, T>> {
+        self.opaque_items.iter().find(|item| item.rectangle.contains(point))
+    }
+
+    /// Returns the area that is drawn more than once by the alpha items.
+    ///
+    /// This is the sum of the areas of the alpha items minus the area of their union. The
+    /// opaque items are not taken into account.
+    pub fn alpha_overdraw(&self) -> T::Area {
+        let total: T::Area = self.alpha_items.iter().map(|item| area(&item.rectangle)).sum();
+        let mut union = Vec::new();
+        self.alpha_union(&mut union);
+        let union_area: T::Area = union.iter().map(area).sum();
+
+        if union_area < total { total - union_area } else { T::Area::zero() }
+    }
+
+    /// Returns the sum of the areas of the opaque items.
+    ///
+    /// This is the area of their union unless some of them overlap, which only happens
+    /// with `add_unsplittable`, `ItemKind::OpaqueNonOccluding` or capped occluders, see
+    /// `occluder_at`.
+    pub fn opaque_area(&self) -> T::Area {
+        self.opaque_items.iter().map(|item| area(&item.rectangle)).sum()
+    }
+
+    /// Returns the fraction of the area of the added rectangles that was culled, between
+    /// 0.0 and 1.0.
+    ///
+    /// A value close to 1.0 means that most of the area was culled while a value close to 0.0
+    /// means that culling didn't help. Rectangles added with `ItemKind::OccluderOnly` are not
+    /// taken into account since they aren't rendered.
+    pub fn cull_efficiency(&self) -> f32 {
+        let input_area = T::area_to_f64(self.input_area);
+        if input_area <= 0.0 {
+            return 0.0;
+        }
+
+        let output_area: T::Area = self
+            .opaque_items
+            .iter()
+            .chain(self.alpha_items.iter())
+            .map(|item| area(&item.rectangle))
+            .sum();
+
+        (1.0 - T::area_to_f64(output_area) / input_area).max(0.0) as f32
+    }
+
+    /// Returns the boundary of the union of the opaque items as a list of closed loops.
+    ///
+    /// This includes the boundaries of holes, which have the opposite winding of the outer
+    /// boundaries. This is useful to draw a border around the opaque content or to generate
+    /// a clip path.
+    pub fn opaque_outline(&self) -> Vec<Vec<euclid::Point2D<T, U>>> {
+        let rects: Vec<Box2D<T>> = self.opaque_items.iter().map(|item| item.rectangle.to_untyped()).collect();
+        outline::rectilinear_outline(&rects)
+            .into_iter()
+            .map(|l| l.into_iter().map(euclid::Point2D::from_untyped).collect())
+            .collect()
+    }
+
+    /// Returns the largest rectangle in the viewport that isn't covered by an occluder, or
+    /// `None` if there is no space left.
+    ///
+    /// The search is exact and takes O(n²) time for n occluders. This is useful to find a
+    /// place for new content in the remaining free space.
+    pub fn largest_empty_rect(&self, viewport: euclid::Box2D<T, U>) -> Option<euclid::Box2D<T, U>> {
+        empty_rect::largest_empty_rect(&self.occluders.occluders, &viewport.to_untyped())
+            .map(|rect| euclid::Box2D::from_untyped(&rect))
+    }
+
+    /// Returns the area of the alpha items behind the alpha items of the provided key that
+    /// they would hide if they were opaque.
+    ///
+    /// This is a what-if analysis over the current results to help decide which rectangles
+    /// are worth adding as opaque. Opaque items and alpha items of the same key aren't
+    /// taken into account.
+    pub fn promotion_benefit(&self, key: K) -> T::Area
+    where
+        K: PartialEq,
+    {
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        let mut benefit = T::Area::zero();
+        for (idx, item) in self.alpha_items.iter().enumerate() {
+            if item.key == key {
+                continue;
+            }
+
+            // The alpha items of the key that are in front of this one.
+            let occluders = self.alpha_items[..idx]
+                .iter()
+                .filter(|other| other.key == key)
+                .map(|other| other.rectangle.to_untyped());
+
+            fragments.clear();
+            cull_fragments(occluders, &item.rectangle.to_untyped(), &mut fragments);
+            let visible: T::Area = fragments.iter().map(area).sum();
+            benefit = benefit + area(&item.rectangle) - visible;
+        }
+
+        benefit
+    }
+
+    /// Returns the bounding box of the visible items of each key.
+    ///
+    /// Keys that don't have any visible item are not in the map.
+    pub fn visible_bounds_by_key(&self) -> HashMap<K, euclid::Box2D<T, U>>
+    where
+        K: Hash + Eq,
+    {
+        let mut bounds: HashMap<K, euclid::Box2D<T, U>> = HashMap::new();
+        for item in self.opaque_items.iter().chain(self.alpha_items.iter()) {
+            bounds
+                .entry(item.key)
+                .and_modify(|b| *b = b.union(&item.rectangle))
+                .or_insert(item.rectangle);
+        }
+
+        bounds
+    }
+
+    /// Append the area of the provided rect that each occluder hides to the output vector,
+    /// without adding the rect.
+    ///
+    /// Occluders are applied in the order they were added and each of them only sees the
+    /// parts that the previous ones left visible, so the front-most occluders are credited
+    /// first. Consecutive occluders with the same key are reported together. Occluders that
+    /// were provided to `cull_against` don't have a key and are not reported.
+    pub fn occlusion_breakdown(&self, rect: &euclid::Box2D<T, U>, output: &mut Vec<(K, T::Area)>)
+    where
+        K: PartialEq,
+    {
+        let rect = &match self.clip_rect(&rect.to_untyped()) {
+            Some(clipped) => clipped,
+            None => return,
+        };
+        let transform = &self.transform;
+        let margin = self.occluder_dilation;
+        let visible_area = |fragments: &SmallVec<[Box2D<T>; N]>| -> T::Area {
+            fragments.iter().map(|f| area(&from_cull_space(transform, f))).sum()
+        };
+
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        fragments.push(to_cull_space(transform, rect));
+        let mut previous_area = visible_area(&fragments);
+
+        let start = output.len();
+        for (occluder, key) in self.occluders.occluders.iter().zip(&self.occluder_keys) {
+            if fragments.is_empty() {
+                break;
+            }
+            let occluder = to_cull_space(transform, occluder).inflate(margin, margin);
+            if occluder.is_empty() || !fragments.iter().any(|f| f.intersects(&occluder)) {
+                continue;
+            }
+
+            apply_occluder_with_epsilon::<_, _, E, _>(&occluder, &mut fragments, self.split_strategy);
+            let new_area = visible_area(&fragments);
+            let removed = previous_area - new_area;
+            previous_area = new_area;
+
+            let key = match key {
+                Some(key) => *key,
+                None => continue,
+            };
+            match output[start..].last_mut() {
+                Some(last) if last.0 == key => last.1 = last.1 + removed,
+                _ => output.push((key, removed)),
+            }
+        }
+    }
+
+    // The sum of the areas of the visible parts of the rect, in user space.
+    fn visible_area(&self, rect: &Box2D<T>) -> T::Area {
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        self.cull_clipped(rect, &mut fragments);
+
+        let transform = &self.transform;
+        fragments.iter().map(|fragment| area(&from_cull_space(transform, fragment))).sum()
+    }
+
+    // Same as `cull` for the part of the rect inside of the clips, for the methods that
+    // test rectangles without adding them.
+    fn cull_clipped<F: Fragment<T>, B: FragmentBuffer<F>>(&self, rect: &Box2D<T>, fragments: &mut B) {
+        if let Some(clipped) = self.clip_rect(rect) {
+            self.cull(&clipped, fragments);
+        }
+    }
+
+    // Write the visible parts of the rect into the fragment list, in culling space.
+    fn cull<F: Fragment<T>, B: FragmentBuffer<F>>(&self, rect: &Box2D<T>, fragments: &mut B) {
+        if is_degenerate(rect) {
+            return;
+        }
+
+        let transform = &self.transform;
+        if let Some(grid) = &self.occupancy {
+            if grid.mask & grid.cells(rect) == 0 && self.occluder_dilation <= T::zero() {
+                fragments.push(F::new(to_cull_space(transform, rect), FragmentOrigin::Unsplit));
+                return;
+            }
+        }
+
+        // Large occluders often hide many small rectangles, in which case there is no need
+        // to split them against the occluders that come before.
+        let margin = self.occluder_dilation;
+        if margin >= T::zero() && self.occluders.occluders.iter().any(|occluder| contains(occluder, rect)) {
+            return;
+        }
+
+        cull_fragments_with_epsilon::<T, F, E, _>(
+            self.occluders.occluders.iter().map(|occluder| {
+                to_cull_space(transform, occluder).inflate(margin, margin)
+            }),
+            &to_cull_space(transform, rect),
+            fragments,
+            self.split_strategy,
+        );
+    }
+
+    /// The visible opaque rectangles (front-to-back order).
+    pub fn opaque_items(&self) -> &[Item<K, D, U, T>] {
+        &self.opaque_items
+    }
+
+    /// The visible non-opaque rectangles (front-to-back order).
+    pub fn alpha_items(&self) -> &[Item<K, D, U, T>] {
+        &self.alpha_items
+    }
+
+    /// The visible opaque and non-opaque rectangles.
+    ///
+    /// Equivalent to `(self.opaque_items(), self.alpha_items())`.
+    #[allow(clippy::type_complexity)]
+    pub fn items(&self) -> (&[Item<K, D, U, T>], &[Item<K, D, U, T>]) {
+        (&self.opaque_items, &self.alpha_items)
+    }
+
+    /// Copies the opaque and alpha items into a serializable snapshot.
+    #[cfg(feature = "serde")]
+    pub fn snapshot(&self) -> Snapshot<K, D, U, T> {
+        Snapshot {
+            opaque_items: self.opaque_items.clone(),
+            alpha_items: self.alpha_items.clone(),
+        }
+    }
+
+    /// The opaque and alpha items of the provided key.
+    ///
+    /// The boolean is true for opaque items. This scans both lists.
+    pub fn fragments_of(&self, key: K) -> impl Iterator<Item = (&Item<K, D, U, T>, bool)>
+    where
+        K: PartialEq,
+    {
+        let opaque = self.opaque_items.iter().map(|item| (item, true));
+        let alpha = self.alpha_items.iter().map(|item| (item, false));
+
+        opaque.chain(alpha).filter(move |(item, _)| item.key == key)
+    }
+
+    /// Sort the opaque items top-to-bottom then left-to-right.
+    ///
+    /// Opaque items don't overlap so their order doesn't affect rendering, however
+    /// a spatially coherent order can be friendlier to GPU caches.
+    /// There is no equivalent for alpha items since reordering them would break
+    /// blending.
+    pub fn sort_opaque_items_spatially(&mut self) {
+        sort_spatially(&mut self.opaque_items);
+    }
+
+    /// Merge pairs of opaque items that share a full edge into a single item, until no
+    /// more items can be merged.
+    ///
+    /// Only the items with the same key and data are merged, including items of different
+    /// rectangles. The original rectangle of a merged item is the union of the originals
+    /// of its parts, and it is marked as `FragmentOrigin::Unsplit` if it covers all of it.
+    /// This reduces the number of items after many splits but doesn't undo every split,
+    /// for example an L-shape remains two items. The order of the items can change.
+    pub fn coalesce_opaque(&mut self)
+    where
+        K: PartialEq,
+        D: PartialEq,
+    {
+        let items = &mut self.opaque_items;
+        let mut merged = true;
+        while merged {
+            merged = false;
+            let mut i = 0;
+            while i < items.len() {
+                let mut j = i + 1;
+                while j < items.len() {
+                    let (a, b) = (&items[i], &items[j]);
+                    if a.key == b.key && a.data == b.data && share_edge(&a.rectangle, &b.rectangle) {
+                        let other = items.swap_remove(j);
+                        let item = &mut items[i];
+                        item.rectangle = item.rectangle.union(&other.rectangle);
+                        item.original = item.original.union(&other.original);
+                        if item.rectangle == item.original {
+                            item.origin = FragmentOrigin::Unsplit;
+                        }
+                        merged = true;
+                    } else {
+                        j += 1;
+                    }
+                }
+                i += 1;
+            }
+        }
+    }
+
+    /// Appends to `out` the parts of the viewport that aren't covered by any opaque or
+    /// alpha item.
+    ///
+    /// This is useful to only clear the parts of the screen that need it.
+    pub fn uncovered_region(&self, viewport: euclid::Box2D<T, U>, out: &mut Vec<euclid::Box2D<T, U>>) {
+        let mut fragments: SmallVec<[Box2D<T>; N]> = SmallVec::new();
+        let items = self.opaque_items.iter().chain(self.alpha_items.iter());
+        cull_fragments(items.map(|item| item.rectangle.to_untyped()), &viewport.to_untyped(), &mut fragments);
+
+        out.extend(fragments.iter().map(euclid::Box2D::from_untyped));
+    }
+
+    /// Restrict the opaque and alpha items to the union of the provided regions.
+    ///
+    /// Each item is replaced with its intersection with each of the regions it overlaps,
+    /// preserving the order of the lists. The regions are expected to not overlap,
+    /// otherwise the resulting items would overlap as well.
+    ///
+    /// Rectangles added afterwards are culled against the occluders from before the
+    /// clipping and are not clipped.
+    pub fn clip_results_multi(&mut self, regions: &[euclid::Box2D<T, U>]) {
+        clip_items(&mut self.opaque_items, regions);
+        clip_items(&mut self.alpha_items, regions);
+    }
+
+    /// Round the coordinates of the visible items to integers, for example to use them as
+    /// scissor rectangles.
+    ///
+    /// Since all coordinates are rounded the same way, edges that are shared by adjacent
+    /// items stay shared. Items that become empty are removed.
+    pub fn round_output(&mut self, mode: RoundMode) {
+        for items in [&mut self.opaque_items, &mut self.alpha_items] {
+            for item in items.iter_mut() {
+                item.rectangle = mode.apply(&item.rectangle);
+            }
+            items.retain(|item| !item.rectangle.is_empty());
+        }
+    }
+
+    /// Resets the builder to its initial state, preserving memory allocations.
+    pub fn clear(&mut self) {
+        self.opaque_items.clear();
+        self.alpha_items.clear();
+        self.occluders.clear();
+        self.occluder_keys.clear();
+        self.translucent.clear();
+        self.commands.clear();
+        self.input_area = T::Area::zero();
+        self.clip_stack.clear();
+        self.locked = false;
+        if let Some(grid) = &mut self.occupancy {
+            grid.mask = 0;
+        }
+        #[cfg(debug_assertions)]
+        {
+            self.last_depth = f32::NEG_INFINITY;
+        }
+    }
+
+    /// Same as `clear` but also grows the allocations to hold at least the provided number
+    /// of opaque and alpha items.
+    ///
+    /// This avoids reallocating while adding rectangles when the next scene is expected to
+    /// be larger than the previous ones.
+    pub fn clear_and_reserve(&mut self, opaque: usize, alpha: usize) {
+        self.clear();
+        self.opaque_items.reserve(opaque);
+        self.alpha_items.reserve(alpha);
+        self.occluders.occluders.reserve(opaque);
+        self.occluders.priorities.reserve(opaque);
+        self.occluder_keys.reserve(opaque);
+    }
+
+    pub fn dump_as_svg(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        self.write_svg(output, false)
+    }
+
+    /// Same as `dump_as_svg` but also draws the original rectangles of the items as dashed
+    /// outlines behind them, which shows the parts that were culled.
+    pub fn dump_as_svg_with_originals(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        self.write_svg(output, true)
+    }
+
+    fn write_svg(&self, output: &mut dyn std::io::Write, originals: bool) -> std::io::Result<()> {
+        use svg_fmt::*;
+
+        let mut w: f32 = 0.0;
+        let mut h: f32 = 0.0;
+
+        for item in self.opaque_items.iter().chain(self.alpha_items.iter()) {
+            let rect = if originals { item.rectangle.union(&item.original) } else { item.rectangle };
+            w = w.max(rect.max.x.to_f64() as f32);
+            h = h.max(rect.max.y.to_f64() as f32);
+        }
+
+        writeln!(output, "{}", BeginSvg { w, h } )?;
+
+        if originals {
+            // svg_fmt doesn't support dashes, the group applies them to the outlines.
+            writeln!(output, r#"  <g stroke-dasharray="4 2">"#)?;
+            // Consecutive items often come from the same rectangle, only draw it once.
+            let mut previous = None;
+            for item in self.opaque_items.iter().chain(self.alpha_items.iter()) {
+                if previous == Some(item.original) {
+                    continue;
+                }
+                previous = Some(item.original);
+                let outline = Stroke::Color(rgb(80, 80, 80), 1.0);
+                write_svg_rect(output, &to_svg_rect(&item.original), black(), 0.0, outline)?;
+            }
+            writeln!(output, "  </g>")?;
+        }
+
+        // Use random blue-ish colors for opaque items and and random red-ish colors for
+        // non-opaque ones. The colors are seeded from the item key.
+
+        for (idx, item) in self.opaque_items.iter().enumerate() {
+            let i = (item.key.debug_seed().unwrap_or(idx as u64).wrapping_mul(37) % 100) as u8;
+            let color = rgb(0, i, 150 + i);
+            write_svg_rect(output, &to_svg_rect(&item.rectangle), color, 1.0, Stroke::Color(black(), 1.0))?;
+        }
+
+        for (idx, item) in self.alpha_items.iter().enumerate() {
+            let i = (item.key.debug_seed().unwrap_or(idx as u64).wrapping_mul(37) % 100) as u8;
+            let color = rgb(150 + i, i, 0);
+            write_svg_rect(output, &to_svg_rect(&item.rectangle), color, 0.6, Stroke::Color(black(), 1.0))?;
+        }
+
+        writeln!(output, "{}", EndSvg)    }
+
+    /// Same as `dump_as_svg` but returns the SVG document as a string.
+    pub fn dump_as_svg_string(&self) -> String {
+        let mut output = Vec::new();
+        self.dump_as_svg(&mut output).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+}
+
+// An 8x8 grid over the viewport stored as a bit mask, with the bit of a cell set if
+// an occluder touches it.
+struct OccupancyGrid<T> {
+    viewport: Box2D<T>,
+    mask: u64,
+}
+
+impl<T: Scalar> OccupancyGrid<T> {
+    // The cells touched by the rect. Coordinates are clamped to the viewport so that
+    // rectangles outside of it map to the cells on its border, which keeps the test
+    // conservative.
+    fn cells(&self, rect: &Box2D<T>) -> u64 {
+        let v = &self.viewport;
+        let cell = |x: T, min: T, max: T| -> u64 {
+            let size = max.to_f64() - min.to_f64();
+            if size <= 0.0 {
+                return 0;
+            }
+            (((x.to_f64() - min.to_f64()) / size * 8.0).max(0.0) as u64).min(7)
+        };
+        let x0 = cell(rect.min.x, v.min.x, v.max.x);
+        let x1 = cell(rect.max.x, v.min.x, v.max.x);
+        let y0 = cell(rect.min.y, v.min.y, v.max.y);
+        let y1 = cell(rect.max.y, v.min.y, v.max.y);
+
+        let row = (0xFFu64 >> (7 - (x1 - x0))) << x0;
+        let mut mask = 0;
+        for y in y0..=y1 {
+            mask |= row << (y * 8);
+        }
+
+        mask
+    }
+}
+
+/// Append the rectangles of the items to `coords` as `[min.x, min.y, max.x, max.y]`
+/// and their keys to `keys`.
+///
+/// This matches a typical instance buffer layout and works with the opaque or alpha
+/// items of either builder.
+pub fn write_flat<K: Copy, D, U>(items: &[Item<K, D, U>], coords: &mut Vec<f32>, keys: &mut Vec<K>) {
+    coords.reserve(items.len() * 4);
+    keys.reserve(items.len());
+    for item in items {
+        let r = &item.rectangle;
+        coords.extend_from_slice(&[r.min.x, r.min.y, r.max.x, r.max.y]);
+        keys.push(item.key);
+    }
+}
+
+/// Append one `[min.x, min.y, width, height]` instance per item to `instances` and,
+/// if provided, their keys to `keys`.
+///
+/// This is the layout of instanced quad renderers. Like `write_flat`, this works with
+/// the opaque or alpha items of either builder.
+pub fn write_instances<K: Copy, D, U>(items: &[Item<K, D, U>], instances: &mut Vec<[f32; 4]>, keys: Option<&mut Vec<K>>) {
+    instances.reserve(items.len());
+    for item in items {
+        let r = &item.rectangle;
+        instances.push([r.min.x, r.min.y, r.width(), r.height()]);
+    }
+    if let Some(keys) = keys {
+        keys.extend(items.iter().map(|item| item.key));
+    }
+}
+
+/// Append two triangles (six vertices) per item to `vertices`.
+///
+/// Each rectangle is split along its `min`-`max` diagonal. Like `write_flat`, this works
+/// with the opaque or alpha items of either builder.
+pub fn write_triangles<K, D, U>(items: &[Item<K, D, U>], vertices: &mut Vec<[f32; 2]>) {
+    vertices.reserve(items.len() * 6);
+    for item in items {
+        let r = &item.rectangle;
+        vertices.extend_from_slice(&[
+            [r.min.x, r.min.y],
+            [r.max.x, r.min.y],
+            [r.max.x, r.max.y],
+            [r.min.x, r.min.y],
+            [r.max.x, r.max.y],
+            [r.min.x, r.max.y],
+        ]);
+    }
+}
+
+/// Same as `write_triangles` but appends four vertices per item and six indices into
+/// `vertices`, accounting for the vertices that were already in the buffer.
+pub fn write_indexed_triangles<K, D, U>(items: &[Item<K, D, U>], vertices: &mut Vec<[f32; 2]>, indices: &mut Vec<u32>) {
+    vertices.reserve(items.len() * 4);
+    indices.reserve(items.len() * 6);
+    for item in items {
+        let r = &item.rectangle;
+        let base = vertices.len() as u32;
+        vertices.extend_from_slice(&[
+            [r.min.x, r.min.y],
+            [r.max.x, r.min.y],
+            [r.max.x, r.max.y],
+            [r.min.x, r.max.y],
+        ]);
+        indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+}
+
+/// Write a single filled rectangle in SVG format.
+///
+/// This can be used along with `svg_fmt::BeginSvg` and `svg_fmt::EndSvg` to stream
+/// debugging output without going through `dump_as_svg`.
+pub fn write_svg_rect(
+    output: &mut dyn std::io::Write,
+    rect: &Box2D<f32>,
+    color: svg_fmt::Color,
+    opacity: f32,
+    stroke: svg_fmt::Stroke,
+) -> std::io::Result<()> {
+    writeln!(
+        output,
+        r#"    {}"#,
+        svg_fmt::rectangle(rect.min.x, rect.min.y, rect.width(), rect.height())
+            .fill(color)
+            .opacity(opacity)
+            .stroke(stroke)
+    )
+}
+
+// SVG coordinates are written as f32 whatever the scalar type.
+fn to_svg_rect<T: Scalar, U>(rect: &euclid::Box2D<T, U>) -> Box2D<f32> {
+    Box2D {
+        min: point2(rect.min.x.to_f64() as f32, rect.min.y.to_f64() as f32),
+        max: point2(rect.max.x.to_f64() as f32, rect.max.y.to_f64() as f32),
+    }
+}
+
+impl<K: Key, E: Epsilon, D: Copy, U, const N: usize, T: Scalar> Default for FrontToBackBuilder<K, E, D, U, N, T> {
+    fn default() -> Self {
+        Self::with_capacity_and_epsilon(0, 0)
+    }
+}
+
+/// A set of opaque rectangles that can be tested against without being modified.
+///
+/// This is useful when a stable set of occluders is used to cull transient rectangles
+/// each frame, without having to re-add the occluders to a builder.
+///
+/// Occluders are allowed to overlap, although it makes the culling more expensive.
+///
+/// The `T` parameter is the type of the coordinates, see `Scalar`.
+#[derive(Clone, Debug, Default)]
+pub struct OccluderSet<T = f32> {
+    occluders: Vec<Box2D<T>>,
+    // The priority of each occluder, in decreasing order.
+    priorities: Vec<f32>,
+}
+
+impl OccluderSet {
+    /// Constructor.
+    pub fn new() -> Self {
+        OccluderSet {
+            occluders: Vec::new(),
+            priorities: Vec::new(),
+        }
+    }
+
+    /// Pre-allocating constructor.
+    pub fn with_capacity(cap: usize) -> Self {
+        OccluderSet {
+            occluders: Vec::with_capacity(cap),
+            priorities: Vec::with_capacity(cap),
+        }
+    }
+}
+
+impl<T: Scalar> OccluderSet<T> {
+    /// Add an opaque rectangle to the set.
+    ///
+    /// It is tested after the occluders that were added with a priority.
+    pub fn add(&mut self, rect: &Box2D<T>) {
+        self.occluders.push(*rect);
+        self.priorities.push(f32::NEG_INFINITY);
+    }
+
+    /// Add an opaque rectangle that is tested before the occluders of lower priority.
+    ///
+    /// The order in which occluders are tested doesn't affect the results, but testing
+    /// the ones that hide the most first lets fully occluded rectangles be rejected sooner.
+    /// Occluders of equal priority are tested in the order they were added. A NaN priority
+    /// is treated as the lowest one, like the occluders added with `add`.
+    pub fn add_with_priority(&mut self, rect: &Box2D<T>, priority: f32) {
+        let priority = if priority.is_nan() { f32::NEG_INFINITY } else { priority };
+        let idx = self.priorities.partition_point(|&p| p >= priority);
+        self.occluders.insert(idx, *rect);
+        self.priorities.insert(idx, priority);
+    }
+
+    /// The occluding rectangles.
+    pub fn occluders(&self) -> &[Box2D<T>] {
+        &self.occluders
+    }
+
+    /// Returns true if the provided rect is at least partially visible.
+    pub fn test(&self, rect: &Box2D<T>) -> bool {
+        test_occluders(&self.occluders, rect)
+    }
+
+    /// Returns the visible parts of the provided rect.
+    pub fn cull(&self, rect: &Box2D<T>) -> Vec<Box2D<T>> {
+        let mut output = Vec::new();
+        self.cull_into(rect, &mut output);
+
+        output
+    }
+
+    /// Appends the visible parts of the provided rect to the output vector.
+    ///
+    /// Returns true the rectangle is at least partially visible.
+    pub fn cull_into(&self, rect: &Box2D<T>, output: &mut Vec<Box2D<T>>) -> bool {
+        let mut fragments: SmallVec<[Box2D<T>; INLINE_FRAGMENT_CAPACITY]> = SmallVec::new();
+        cull_fragments(self.occluders.iter().cloned(), rect, &mut fragments);
+        output.extend_from_slice(&fragments);
+
+        !fragments.is_empty()
+    }
+
+    /// Removes all occluders, preserving memory allocations.
+    pub fn clear(&mut self) {
+        self.occluders.clear();
+        self.priorities.clear();
+    }
+}
+
+/// A lightweight alternative to `FrontToBackBuilder` that streams the visible parts of the
+/// rectangles instead of storing them.
+///
+/// Rectangles are provided in front-to-back order. Only the occluders are kept in memory,
+/// which makes it suitable for culling transient rectangles each frame. The `T` parameter
+/// is the type of the coordinates, see `Scalar`.
+#[derive(Clone, Debug, Default)]
+pub struct Culler<T = f32> {
+    occluders: OccluderSet<T>,
+}
+
+impl Culler {
+    /// Constructor.
+    pub fn new() -> Self {
+        Culler {
+            occluders: OccluderSet::new(),
+        }
+    }
+
+    /// Pre-allocating constructor.
+    pub fn with_capacity(cap: usize) -> Self {
+        Culler {
+            occluders: OccluderSet::with_capacity(cap),
+        }
+    }
+}
+
+impl<T: Scalar> Culler<T> {
+    /// Add a rectangle and pass each of its visible parts to the callback.
+    ///
+    /// The visible parts of opaque rectangles are added to the occluders.
+    ///
+    /// Returns true if the rectangle is at least partially visible.
+    pub fn add(&mut self, rect: &Box2D<T>, is_opaque: bool, mut callback: impl FnMut(&Box2D<T>)) -> bool {
+        let mut fragments: SmallVec<[Box2D<T>; INLINE_FRAGMENT_CAPACITY]> = SmallVec::new();
+        cull_fragments(self.occluders.occluders.iter().cloned(), rect, &mut fragments);
+
+        for fragment in &fragments {
+            callback(fragment);
+            if is_opaque {
+                self.occluders.add(fragment);
+            }
+        }
+
+        !fragments.is_empty()
+    }
+
+    /// The occluders accumulated so far.
+    pub fn occluders(&self) -> &OccluderSet<T> {
+        &self.occluders
+    }
+
+    /// Removes all occluders, preserving memory allocations.
+    pub fn clear(&mut self) {
+        self.occluders.clear();
+    }
+}
+
+/// A streaming culler for a viewport that wraps around, such that content past one of
+/// its edges reappears at the opposite edge.
+///
+/// Occluders are tiled over the plane with a period equal to the size of the viewport,
+/// so for example an occluder at `x = 0` also hides content at `x = width`. Rectangles
+/// that straddle a seam are split accordingly.
+#[derive(Clone, Debug)]
+pub struct WrappingCuller {
+    size: Size2D<f32>,
+    occluders: Vec<Box2D<f32>>,
+}
+
+impl WrappingCuller {
+    /// Constructor.
+    ///
+    /// The size of the viewport must be positive.
+    pub fn new(size: Size2D<f32>) -> Self {
+        debug_assert!(size.width > 0.0 && size.height > 0.0);
+        WrappingCuller {
+            size,
+            occluders: Vec::new(),
+        }
+    }
+
+    /// Add a rectangle and pass each of its visible parts to the callback.
+    ///
+    /// Rectangles that are wider or taller than the viewport cover the same area more than
+    /// once, so they are clamped to a single period starting at their minimum and only the
+    /// visible parts within it are reported. Empty and non-finite rectangles are ignored.
+    ///
+    /// See `Culler::add`.
+    pub fn add(&mut self, rect: &Box2D<f32>, is_opaque: bool, mut callback: impl FnMut(&Box2D<f32>)) -> bool {
+        if is_degenerate(rect) {
+            return false;
+        }
+
+        let (w, h) = (self.size.width, self.size.height);
+        let rect = &Box2D {
+            min: rect.min,
+            max: point2(rect.max.x.min(rect.min.x + w), rect.max.y.min(rect.min.y + h)),
+        };
+        // The copies of each occluder that can intersect the rect. Occluders are no larger
+        // than a period either, so there are at most a few of them on each axis.
+        let copies = self.occluders.iter().flat_map(move |o| {
+            let x_range = ((rect.min.x - o.max.x) / w).floor() as i64..=((rect.max.x - o.min.x) / w).ceil() as i64;
+            let y_range = ((rect.min.y - o.max.y) / h).floor() as i64..=((rect.max.y - o.min.y) / h).ceil() as i64;
+            y_range.flat_map(move |y| {
+                x_range.clone().map(move |x| o.translate(vec2(x as f32 * w, y as f32 * h)))
+            })
+        });
+
+        let mut fragments: SmallVec<[Box2D<f32>; INLINE_FRAGMENT_CAPACITY]> = SmallVec::new();
+        cull_fragments(copies, rect, &mut fragments);
+
+        for fragment in &fragments {
+            callback(fragment);
+            if is_opaque {
+                self.occluders.push(*fragment);
+            }
+        }
+
+        !fragments.is_empty()
+    }
+
+    /// The occluders accumulated so far, before tiling.
+    pub fn occluders(&self) -> &[Box2D<f32>] {
+        &self.occluders
+    }
+
+    /// Removes all occluders, preserving memory allocations.
+    pub fn clear(&mut self) {
+        self.occluders.clear();
+    }
+}
+
+impl<T> From<Vec<Box2D<T>>> for OccluderSet<T> {
+    fn from(occluders: Vec<Box2D<T>>) -> Self {
+        let priorities = vec![f32::NEG_INFINITY; occluders.len()];
+        OccluderSet { occluders, priorities }
+    }
+}
+
+/// Returns true if the provided rect is at least partially visible through a borrowed
+/// list of occluders.
+///
+/// This is equivalent to `OccluderSet::test` without requiring ownership of the occluders.
+pub fn test_occluders<T: Scalar>(occluders: &[Box2D<T>], rect: &Box2D<T>) -> bool {
+    let mut fragments: SmallVec<[Box2D<T>; INLINE_FRAGMENT_CAPACITY]> = SmallVec::new();
+    cull_fragments(occluders.iter().cloned(), rect, &mut fragments);
+
+    !fragments.is_empty()
+}
+
+// Write the visible parts of the rect into the fragment list.
+fn cull_fragments<T: Scalar, F: Fragment<T>, B: FragmentBuffer<F>>(
+    occluders: impl Iterator<Item = Box2D<T>>,
+    rect: &Box2D<T>,
+    fragments: &mut B,
+) {
+    cull_fragments_with_epsilon::<T, F, ZeroEpsilon, B>(occluders, rect, fragments, SplitStrategy::HorizontalBands);
+}
+
+fn cull_fragments_with_epsilon<T: Scalar, F: Fragment<T>, E: Epsilon, B: FragmentBuffer<F>>(
+    occluders: impl Iterator<Item = Box2D<T>>,
+    rect: &Box2D<T>,
+    fragments: &mut B,
+    strategy: SplitStrategy,
+) {
+    fragments.push(F::new(*rect, FragmentOrigin::Unsplit));
+
+    for occluder in occluders {
+        if fragments.is_empty() {
+            break;
+        }
+        // Empty occluders don't hide anything but could produce empty fragments.
+        if overlaps::<E, _>(rect, &occluder) && !occluder.is_empty() {
+            apply_occluder_with_epsilon::<T, F, E, B>(&occluder, fragments, strategy);
+        }
+    }
+}
+
+// Areas are computed in a wider type to avoid overflows and precision issues with large
+// coordinates, see `Scalar::Area`.
+fn area<T: Scalar, U>(rect: &euclid::Box2D<T, U>) -> T::Area {
+    T::area(rect)
+}
+
+// The area as a f64, to compute ratios.
+fn area_f64<T: Scalar, U>(rect: &euclid::Box2D<T, U>) -> f64 {
+    T::area_to_f64(T::area(rect))
+}
+
+fn clip_items<K: Copy, D: Copy, U, T: Scalar>(items: &mut Vec<Item<K, D, U, T>>, regions: &[euclid::Box2D<T, U>]) {
+    let mut clipped = Vec::with_capacity(items.len());
+    for item in items.iter() {
+        for region in regions {
+            if let Some(rectangle) = item.rectangle.intersection(region) {
+                clipped.push(Item {
+                    rectangle,
+                    original: item.original,
+                    origin: item.origin,
+                    key: item.key,
+                    data: item.data,
+                });
+            }
+        }
+    }
+
+    *items = clipped;
+}
+
+fn sort_spatially<K, D, U, T: Scalar>(items: &mut [Item<K, D, U, T>]) {
+    items.sort_by(|a, b| {
+        let a = a.rectangle.min;
+        let b = b.rectangle.min;
+        let cmp = |a: T, b: T| a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+        cmp(a.y, b.y).then(cmp(a.x, b.x))
+    });
+}
+
+fn to_cull_space<T: Scalar>(transform: &Option<AxisAlignedTransform>, rect: &Box2D<T>) -> Box2D<T> {
+    match transform {
+        Some(t) => T::transform_box(&t.transform, rect),
+        None => *rect,
+    }
+}
+
+fn from_cull_space<T: Scalar>(transform: &Option<AxisAlignedTransform>, rect: &Box2D<T>) -> Box2D<T> {
+    match transform {
+        Some(t) => T::transform_box(&t.inverse, rect),
+        None => *rect,
+    }
+}
+
+
+// The splitting logic is generic over the fragment type so that the extra bookkeeping
+// is only done when needed.
+trait Fragment<T = f32>: Copy {
+    fn new(rect: Box2D<T>, origin: FragmentOrigin) -> Self;
+    fn rect(&self) -> &Box2D<T>;
+}
+
+impl<T: Scalar> Fragment<T> for Box2D<T> {
+    fn new(rect: Box2D<T>, _: FragmentOrigin) -> Self {
+        rect
+    }
+
+    fn rect(&self) -> &Box2D<T> {
+        self
+    }
+}
+
+// The storage of the fragments while splitting, either inline or provided by the caller,
+// see `FragmentScratch`.
+trait FragmentBuffer<F>: DerefMut<Target = [F]> {
+    fn push(&mut self, fragment: F);
+    fn swap_remove(&mut self, index: usize);
+}
+
+impl<A: Array> FragmentBuffer<A::Item> for SmallVec<A> {
+    fn push(&mut self, fragment: A::Item) {
+        SmallVec::push(self, fragment);
+    }
+
+    fn swap_remove(&mut self, index: usize) {
+        SmallVec::swap_remove(self, index);
+    }
+}
+
+impl<F> FragmentBuffer<F> for Vec<F> {
+    fn push(&mut self, fragment: F) {
+        Vec::push(self, fragment);
+    }
+
+    fn swap_remove(&mut self, index: usize) {
+        Vec::swap_remove(self, index);
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct TaggedFragment<T = f32> {
+    rect: Box2D<T>,
+    origin: FragmentOrigin,
+}
+
+impl<T: Scalar> Fragment<T> for TaggedFragment<T> {
+    fn new(rect: Box2D<T>, origin: FragmentOrigin) -> Self {
+        TaggedFragment { rect, origin }
+    }
+
+    fn rect(&self) -> &Box2D<T> {
+        &self.rect
+    }
+}
+
+// Whether the rect can't be visible. Non-finite coordinates are rejected as well since
+// the comparisons of the splitting don't handle them.
+fn is_degenerate<T: Scalar>(rect: &Box2D<T>) -> bool {
+    let finite = rect.min.x.is_finite()
+        && rect.min.y.is_finite()
+        && rect.max.x.is_finite()
+        && rect.max.y.is_finite();
+    debug_assert!(finite, "non-finite rectangle {:?}", rect);
+
+    !finite || rect.is_empty()
+}
+
+// Whether the clip can contain a visible rectangle.
+fn check_clip<T: Scalar>(clip: &Box2D<T>) -> Result<(), BuilderError> {
+    let finite = clip.min.x.is_finite()
+        && clip.min.y.is_finite()
+        && clip.max.x.is_finite()
+        && clip.max.y.is_finite();
+    if !finite {
+        return Err(BuilderError::NonFiniteClip);
+    }
+    if clip.min.x > clip.max.x || clip.min.y > clip.max.y {
+        return Err(BuilderError::InvertedClip);
+    }
+    if clip.is_empty() {
+        return Err(BuilderError::EmptyClip);
+    }
+
+    Ok(())
+}
+
+// Whether the rects are side by side with an edge of the same length in common.
+fn share_edge<T: Scalar, U>(a: &euclid::Box2D<T, U>, b: &euclid::Box2D<T, U>) -> bool {
+    let same_rows = a.min.y == b.min.y && a.max.y == b.max.y;
+    let same_columns = a.min.x == b.min.x && a.max.x == b.max.x;
+
+    (same_rows && (a.max.x == b.min.x || b.max.x == a.min.x))
+        || (same_columns && (a.max.y == b.min.y || b.max.y == a.min.y))
+}
+
+// Whether the rect is inside of the occluder, including along its edges.
+fn contains<T: Scalar>(occluder: &Box2D<T>, rect: &Box2D<T>) -> bool {
+    occluder.min.x <= rect.min.x
+        && occluder.min.y <= rect.min.y
+        && occluder.max.x >= rect.max.x
+        && occluder.max.y >= rect.max.y
+}
+
+// Whether the rects overlap by more than the epsilon on both axes.
+fn overlaps<E: Epsilon, T: Scalar>(a: &Box2D<T>, b: &Box2D<T>) -> bool {
+    let eps = T::from_epsilon(E::EPS);
+    a.min.x < b.max.x - eps
+        && a.max.x > b.min.x + eps
+        && a.min.y < b.max.y - eps
+        && a.max.y > b.min.y + eps
+}
+
+// Split out the parts of the rects in the provided vector
+fn apply_occluder<T: Scalar, F: Fragment<T>, B: FragmentBuffer<F>>(occluder: &Box2D<T>, rects: &mut B) {
+    apply_occluder_with_epsilon::<T, F, ZeroEpsilon, B>(occluder, rects, SplitStrategy::HorizontalBands);
+}
+
+// Edges of the rects that are within the epsilon of the occluder's are snapped to it, so
+// no band thinner than the epsilon is produced.
+fn apply_occluder_with_epsilon<T: Scalar, F: Fragment<T>, E: Epsilon, B: FragmentBuffer<F>>(
+    occluder: &Box2D<T>,
+    rects: &mut B,
+    strategy: SplitStrategy,
+) {
+    let eps = T::from_epsilon(E::EPS);
+    // Only the `count` rects that are in the vector at this point are processed, new rects
+    // are pushed past them. They are visited in reverse order so that each split rect can
+    // be replaced with the last one, which was already processed. A forward cursor would
+    // have to shift the kept rects, which changes the order of the output fragments.
+    let count = rects.len();
+    for i in (0..count).rev() {
+        let r = *rects[i].rect();
+
+        if !overlaps::<E, _>(&r, occluder) {
+            continue;
+        }
+
+        let top = r.min.y < occluder.min.y - eps && r.max.y > occluder.min.y;
+        let bottom = r.max.y > occluder.max.y + eps && r.min.y < occluder.max.y;
+        let left = r.min.x < occluder.min.x - eps && r.max.x > occluder.min.x;
+        let right = r.max.x > occluder.max.x + eps && r.min.x < occluder.max.x;
+
+        // The boundaries of the occluded part, clamped to the rect.
+        let inner_min_x = if left { occluder.min.x } else { r.min.x };
+        let inner_max_x = if right { occluder.max.x } else { r.max.x };
+        let inner_min_y = if top { occluder.min.y } else { r.min.y };
+        let inner_max_y = if bottom { occluder.max.y } else { r.max.y };
+
+        let mut push = |min_x, min_y, max_x, max_y, origin| {
+            rects.push(F::new(Box2D {
+                min: point2(min_x, min_y),
+                max: point2(max_x, max_y),
+            }, origin));
+        };
+
+        match strategy {
+            SplitStrategy::HorizontalBands => {
+                if top {
+                    push(r.min.x, r.min.y, r.max.x, occluder.min.y, FragmentOrigin::Top);
+                }
+                if bottom {
+                    push(r.min.x, occluder.max.y, r.max.x, r.max.y, FragmentOrigin::Bottom);
+                }
+                if left {
+                    push(r.min.x, inner_min_y, occluder.min.x, inner_max_y, FragmentOrigin::Left);
+                }
+                if right {
+                    push(occluder.max.x, inner_min_y, r.max.x, inner_max_y, FragmentOrigin::Right);
+                }
+            }
+            SplitStrategy::VerticalBands => {
+                if top {
+                    push(inner_min_x, r.min.y, inner_max_x, occluder.min.y, FragmentOrigin::Top);
+                }
+                if bottom {
+                    push(inner_min_x, occluder.max.y, inner_max_x, r.max.y, FragmentOrigin::Bottom);
+                }
+                if left {
+                    push(r.min.x, r.min.y, occluder.min.x, r.max.y, FragmentOrigin::Left);
+                }
+                if right {
+                    push(occluder.max.x, r.min.y, r.max.x, r.max.y, FragmentOrigin::Right);
+                }
+            }
+            SplitStrategy::NinePatch => {
+                let rows = [
+                    (top, r.min.y, occluder.min.y, FragmentOrigin::Top),
+                    (bottom, occluder.max.y, r.max.y, FragmentOrigin::Bottom),
+                ];
+                for &(present, min_y, max_y, origin) in &rows {
+                    if !present {
+                        continue;
+                    }
+                    if left {
+                        push(r.min.x, min_y, occluder.min.x, max_y, origin);
+                    }
+                    push(inner_min_x, min_y, inner_max_x, max_y, origin);
+                    if right {
+                        push(occluder.max.x, min_y, r.max.x, max_y, origin);
+                    }
+                }
+                if left {
+                    push(r.min.x, inner_min_y, occluder.min.x, inner_max_y, FragmentOrigin::Left);
+                }
+                if right {
+                    push(occluder.max.x, inner_min_y, r.max.x, inner_max_y, FragmentOrigin::Right);
+                }
+            }
+        }
+
+        rects.swap_remove(i);
+    }
+}
+
+/// A change to the rectangle of a key in `BackToFrontBuilder::update`, `None` removes it.
+pub type CommandUpdate<K = u64, U = UnknownUnit, T = f32> = (K, Option<(euclid::Box2D<T, U>, bool)>);
+
+// Hashes the commands and their depths.
+type CommandHasher<K, U, T> = fn(&[(euclid::Box2D<T, U>, bool, K)], &[f32]) -> u64;
+
+/// A back-to-front occlusion culling builder provided for convenience.
+///
+/// This builder internally reconstructs front-to-back order at the cost
+/// of some computation overhead and uses FrontToBackBuilder. For maximum
+/// speed it is better to use `FrontToBackBuilder` directly instead.
+///
+/// Like `FrontToBackBuilder`, the `U` parameter is the unit of the coordinates. Use
+/// `default()` to construct builders of other units than `UnknownUnit`.
+pub struct BackToFrontBuilder<K = u64, U = UnknownUnit, T: Scalar = f32> {
+    commands: Vec<(euclid::Box2D<T, U>, bool, K)>,
+    // The depth of each command, NaN if it was added without one.
+    depths: Vec<f32>,
+    builder: FrontToBackBuilder<K, ZeroEpsilon, (), U, INLINE_FRAGMENT_CAPACITY, T>,
+    // The depth of the command each alpha item comes from.
+    alpha_depths: Vec<f32>,
+    // The number of commands left to process if a build is in progress.
+    build_cursor: Option<usize>,
+    retain_commands: bool,
+    // Set when caching is enabled, see `set_cache_enabled`.
+    command_hasher: Option<CommandHasher<K, U, T>>,
+    // The hash of the commands the current results were built from, if they can be reused.
+    cached_hash: Option<u64>,
+    // The index of the command each alpha item comes from.
+    #[cfg(debug_assertions)]
+    alpha_sequence: Vec<usize>,
+}
+
+impl<K: Key> BackToFrontBuilder<K> {
+    /// Constructor.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<K: Key, U, T: Scalar> BackToFrontBuilder<K, U, T> {
+    /// Set the transform from the space of the provided rectangles to the space in which
+    /// the occlusion culling is performed.
+    ///
+    /// See `FrontToBackBuilder::set_transform`.
+    pub fn set_transform(&mut self, transform: AxisAlignedTransform) {
+        self.builder.set_transform(transform);
+        self.cached_hash = None;
+    }
+
+    /// If true, the rectangles are kept after `build()` instead of being cleared, so that
+    /// the same scene can be built again, for example after changing the transform.
+    ///
+    /// Defaults to false.
+    pub fn set_retain_commands(&mut self, retain: bool) {
+        self.retain_commands = retain;
+    }
+
+    /// If true, a build keeps the results of the previous one if the rectangles, their
+    /// depths and keys haven't changed.
+    ///
+    /// This costs a hash of the commands per build and saves the whole build for static
+    /// scenes. Changing the transform or modifying the results, for example with
+    /// `round_output`, invalidates the cache. Defaults to false.
+    pub fn set_cache_enabled(&mut self, enabled: bool)
+    where
+        K: Hash,
+    {
+        self.command_hasher = if enabled { Some(hash_commands::<K, U, T>) } else { None };
+        self.cached_hash = None;
+    }
+
+    /// The rectangles added since the last build, in back-to-front order.
+    ///
+    /// If commands are retained, this also contains the rectangles of the previous builds.
+    pub fn commands(&self) -> &[(euclid::Box2D<T, U>, bool, K)] {
+        &self.commands
+    }
+
+    /// Add a rectangle in back-to-font order.
+    ///
+    /// Computation is deferred to the `build()` method.
+    ///
+    /// Rectangles must not be added while a build started with `build_steps` is in progress.
+    pub fn add(&mut self, rect: &euclid::Box2D<T, U>, is_opaque: bool, key: K) {
+        self.add_with_depth(rect, is_opaque, key, f32::NAN);
+    }
+
+    /// Same as `add` with the depth of the rectangle.
+    ///
+    /// The depth doesn't affect culling, rectangles must still be added in back-to-front
+    /// order. It allows `sort_alpha_ties_by_key` to know which alpha items can be
+    /// reordered.
+    pub fn add_with_depth(&mut self, rect: &euclid::Box2D<T, U>, is_opaque: bool, key: K, depth: f32) {
+        debug_assert!(self.build_cursor.is_none());
+        self.commands.push((*rect, is_opaque, key));
+        self.depths.push(depth);
+    }
+
+    /// Remove and return the most recently added rectangle.
+    ///
+    /// Like `add`, this must not be called while a build is in progress.
+    pub fn pop(&mut self) -> Option<(euclid::Box2D<T, U>, bool, K)> {
+        debug_assert!(self.build_cursor.is_none());
+        self.depths.pop();
+        self.commands.pop()
+    }
+
+    /// Apply the occlusion culling algorithm to the rectangles provided by prior `add`
+    /// invocations.
+    pub fn build(&mut self) {
+        self.build_steps(usize::MAX);
+    }
+
+    /// Replace or remove the rectangles of the provided keys then build.
+    ///
+    /// A changed key keeps the position of its first rectangle in the back-to-front order
+    /// and its other rectangles are removed. Keys that had no rectangle are added in front.
+    /// `None` removes the rectangles of the key. If a key is provided more than once, the
+    /// last change applies.
+    ///
+    /// This requires `set_retain_commands(true)`, so that the rectangles of the keys that
+    /// didn't change are still there from the previous build.
+    pub fn update(&mut self, changed: &[CommandUpdate<K, U, T>])
+    where
+        K: Hash + Eq,
+    {
+        debug_assert!(self.retain_commands, "update requires retained commands");
+        debug_assert!(self.build_cursor.is_none());
+
+        // The change of each key and whether it was applied to an existing rectangle.
+        let mut changes: HashMap<K, _> =
+            changed.iter().map(|&(key, change)| (key, (change, false))).collect();
+
+        // Compact the retained commands in place.
+        let mut kept = 0;
+        for idx in 0..self.commands.len() {
+            let key = self.commands[idx].2;
+            if let Some((change, replaced)) = changes.get_mut(&key) {
+                match change {
+                    Some((rect, is_opaque)) if !*replaced => {
+                        self.commands[idx] = (*rect, *is_opaque, key);
+                        *replaced = true;
+                    }
+                    _ => continue,
+                }
+            }
+            self.commands.swap(kept, idx);
+            self.depths.swap(kept, idx);
+            kept += 1;
+        }
+        self.commands.truncate(kept);
+        self.depths.truncate(kept);
+
+        for &(key, _) in changed {
+            if let Some((Some((rect, is_opaque)), false)) = changes.remove(&key) {
+                self.add(&rect, is_opaque, key);
+            }
+        }
+
+        self.build();
+    }
+
+    /// Same as `build()` but regularly calls `should_cancel` and stops if it returns true.
+    ///
+    /// When cancelled, the partial results are discarded and the rectangles are kept, so
+    /// that the next build starts over.
+    pub fn build_cancellable(&mut self, should_cancel: impl Fn() -> bool) -> Result<(), Cancelled> {
+        // The number of commands processed between two calls to should_cancel.
+        const STEP: usize = 64;
+
+        loop {
+            if should_cancel() {
+                self.build_cursor = None;
+                self.cached_hash = None;
+                self.builder.clear();
+                self.alpha_depths.clear();
+                #[cfg(debug_assertions)]
+                self.alpha_sequence.clear();
+                return Err(Cancelled);
+            }
+            if self.build_steps(STEP) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Incrementally apply the occlusion culling algorithm, processing at most `max_commands`
+    /// rectangles per call.
+    ///
+    /// Returns true when the build is complete. The results are only valid after that,
+    /// which allows spreading the cost of a large build over several frames.
+    pub fn build_steps(&mut self, max_commands: usize) -> bool {
+        let remaining = match self.build_cursor {
+            Some(remaining) => remaining,
+            None => {
+                if let Some(hasher) = self.command_hasher {
+                    let hash = hasher(&self.commands, &self.depths);
+                    if self.cached_hash == Some(hash) {
+                        if !self.retain_commands {
+                            self.commands.clear();
+                            self.depths.clear();
+                        }
+                        return true;
+                    }
+                    // Commands can't change while the build is in progress.
+                    self.cached_hash = Some(hash);
+                }
+
+                let cap = self.commands.len();
+                self.builder.clear();
+                self.alpha_depths.clear();
+                #[cfg(debug_assertions)]
+                self.alpha_sequence.clear();
+                self.builder.opaque_items.reserve(cap);
+                self.builder.alpha_items.reserve(cap);
+                cap
+            }
+        };
+
+        let count = remaining.min(max_commands);
+        for idx in (remaining - count..remaining).rev() {
+            let cmd = &self.commands[idx];
+            self.builder.add(&cmd.0, cmd.1, cmd.2);
+            self.alpha_depths.resize(self.builder.alpha_items.len(), self.depths[idx]);
+            #[cfg(debug_assertions)]
+            self.alpha_sequence.resize(self.builder.alpha_items.len(), idx);
+        }
+
+        let remaining = remaining - count;
+        if remaining > 0 {
+            self.build_cursor = Some(remaining);
+            return false;
+        }
+
+        // No need to reverse the opaque list because it does not
+        // matter for rendering.
+        self.builder.alpha_items.reverse();
+        self.alpha_depths.reverse();
+        #[cfg(debug_assertions)]
+        self.alpha_sequence.reverse();
+        if !self.retain_commands {
+            self.commands.clear();
+            self.depths.clear();
+        }
+        self.build_cursor = None;
+
+        true
+    }
+
+    /// The visible opaque rectangles.
+    ///
+    /// Opaque items are only accessible after `build()`.
+    pub fn opaque_items(&self) -> &[Item<K, (), U, T>] {
+        &self.builder.opaque_items
+    }
+
+    /// The visible non-opaque rectangles in back-to-front order.
+    ///
+    /// Opaque items are only accessible after `build()`.
+    pub fn alpha_items(&self) -> &[Item<K, (), U, T>] {
+        &self.builder.alpha_items
+    }
+
+    /// The visible opaque and non-opaque rectangles.
+    ///
+    /// Equivalent to `(self.opaque_items(), self.alpha_items())`.
+    #[allow(clippy::type_complexity)]
+    pub fn items(&self) -> (&[Item<K, (), U, T>], &[Item<K, (), U, T>]) {
+        self.builder.items()
+    }
+
+    /// Copies the opaque and alpha items into a serializable snapshot.
+    #[cfg(feature = "serde")]
+    pub fn snapshot(&self) -> Snapshot<K, (), U, T> {
+        self.builder.snapshot()
+    }
+
+    /// The visible rectangles in the order they should be rendered: opaque items first,
+    /// then alpha items in back-to-front order.
+    ///
+    /// The boolean is true for opaque items, which typically use a different pipeline.
+    pub fn draw_order(&self) -> impl Iterator<Item = (&Item<K, (), U, T>, bool)> {
+        let opaque = self.builder.opaque_items.iter().map(|item| (item, true));
+        let alpha = self.builder.alpha_items.iter().map(|item| (item, false));
+
+        opaque.chain(alpha)
+    }
+
+    /// The opaque and alpha items of the provided key.
+    ///
+    /// See `FrontToBackBuilder::fragments_of`.
+    pub fn fragments_of(&self, key: K) -> impl Iterator<Item = (&Item<K, (), U, T>, bool)>
+    where
+        K: PartialEq,
+    {
+        self.builder.fragments_of(key)
+    }
+
+    /// Sort the opaque items top-to-bottom then left-to-right.
+    ///
+    /// See `FrontToBackBuilder::sort_opaque_items_spatially`.
+    pub fn sort_opaque_items_spatially(&mut self) {
+        self.builder.sort_opaque_items_spatially();
+        self.cached_hash = None;
+    }
+
+    /// Round the coordinates of the visible items to integers.
+    ///
+    /// See `FrontToBackBuilder::round_output`.
+    pub fn round_output(&mut self, mode: RoundMode) {
+        let keep: Vec<bool> = self
+            .builder
+            .alpha_items
+            .iter()
+            .map(|item| !mode.apply(&item.rectangle).is_empty())
+            .collect();
+        let mut idx = 0;
+        self.alpha_depths.retain(|_| {
+            idx += 1;
+            keep[idx - 1]
+        });
+        #[cfg(debug_assertions)]
+        {
+            let mut idx = 0;
+            self.alpha_sequence.retain(|_| {
+                idx += 1;
+                keep[idx - 1]
+            });
+        }
+        self.builder.round_output(mode);
+        self.cached_hash = None;
+    }
+
+    /// Sort the alpha items that come from rectangles of equal depth by key, keeping the
+    /// order of the items of each key.
+    ///
+    /// Items at equal depth don't overlap in a way that matters for blending, so grouping
+    /// them by key reduces state changes in the renderer. Only the items of consecutive
+    /// rectangles that were added with the same depth through `add_with_depth` are
+    /// reordered, the order between different depths is preserved.
+    pub fn sort_alpha_ties_by_key(&mut self)
+    where
+        K: Ord,
+    {
+        self.cached_hash = None;
+        let items = &mut self.builder.alpha_items;
+        let depths = &self.alpha_depths;
+        let mut start = 0;
+        while start < items.len() {
+            let mut end = start + 1;
+            while end < items.len() && depths[end] == depths[start] {
+                end += 1;
+            }
+
+            if end - start > 1 {
+                let mut order: Vec<usize> = (start..end).collect();
+                order.sort_by_key(|&i| items[i].key);
+                let sorted: Vec<Item<K, (), U, T>> = order.iter().map(|&i| items[i]).collect();
+                items[start..end].copy_from_slice(&sorted);
+                #[cfg(debug_assertions)]
+                {
+                    let alpha_sequence = &mut self.alpha_sequence;
+                    let sequence: Vec<usize> = order.iter().map(|&i| alpha_sequence[i]).collect();
+                    alpha_sequence[start..end].copy_from_slice(&sequence);
+                }
+            }
+
+            start = end;
+        }
+    }
+
+    /// Asserts that the alpha items are in back-to-front order, in debug builds.
+    ///
+    /// This does nothing in release builds.
+    pub fn debug_assert_alpha_ordered(&self) {
+        #[cfg(debug_assertions)]
+        {
+            debug_assert_eq!(self.alpha_sequence.len(), self.builder.alpha_items.len());
+            for (i, pair) in self.alpha_sequence.windows(2).enumerate() {
+                // Items at equal depth can be in any order.
+                let tie = self.alpha_depths[i] == self.alpha_depths[i + 1];
+                debug_assert!(
+                    tie || pair[0] <= pair[1],
+                    "alpha item {} (command {}) is rendered before alpha item {} (command {})",
+                    i, pair[0], i + 1, pair[1],
+                );
+            }
+        }
+    }
+}
+
+fn hash_commands<K: Hash, U, T: Scalar>(commands: &[(euclid::Box2D<T, U>, bool, K)], depths: &[f32]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for ((rect, is_opaque, key), depth) in commands.iter().zip(depths) {
+        [rect.min.x, rect.min.y, rect.max.x, rect.max.y].map(T::to_bits).hash(&mut hasher);
+        depth.to_bits().hash(&mut hasher);
+        is_opaque.hash(&mut hasher);
+        key.hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
+
+impl<K: Key, U, T: Scalar> Default for BackToFrontBuilder<K, U, T> {
+    fn default() -> Self {
+        BackToFrontBuilder {
+            commands: Vec::new(),
+            depths: Vec::new(),
+            builder: FrontToBackBuilder::default(),
+            alpha_depths: Vec::new(),
+            build_cursor: None,
+            retain_commands: false,
+            command_hasher: None,
+            cached_hash: None,
+            #[cfg(debug_assertions)]
+            alpha_sequence: Vec::new(),
+        }
+    }
+}
+
+#[test]
+fn basic() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, 1);
+
+    assert_eq!(builder.opaque_items(), &[Item { rectangle: Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, original: Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, origin: FragmentOrigin::Unsplit, key: 0, data: () }]);
+    assert_eq!(builder.alpha_items(), &[
+        Item { rectangle: Box2D { min: point2(100.0, 50.0), max: point2(150.0, 100.0) }, original: Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, origin: FragmentOrigin::Right, key: 1, data: () },
+        Item { rectangle: Box2D { min: point2(50.0, 100.0), max: point2(150.0, 150.0) }, original: Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, origin: FragmentOrigin::Bottom, key: 1, data: () },
+    ]);
+}
+
+#[test]
+fn fully_occluded_1() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 1);
+    builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) }, false, 2);
+
+    assert!(builder.alpha_items().is_empty());
+}
+
+#[test]
+fn fully_occluded_2() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 100.0), max: point2(100.0, 200.0) }, true, 0);
+    builder.add(&Box2D { min: point2(100.0, 100.0), max: point2(200.0, 200.0) }, true, 0);
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 200.0) }, false, 1);
+    builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(190.0, 190.0) }, false, 2);
+
+    assert!(builder.alpha_items().is_empty());
+}
+
+#[test]
+fn rotated_cull_space() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_transform(AxisAlignedTransform::rotation(1));
+
+    builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 150.0) }, false, 1);
+
+    // The bands are vertical in user space.
+    let alpha = builder.alpha_items();
+    assert_eq!(alpha.len(), 4);
+    assert!(alpha.contains(&Item { rectangle: Box2D { min: point2(0.0, 0.0), max: point2(50.0, 150.0) }, original: Box2D { min: point2(0.0, 0.0), max: point2(150.0, 150.0) }, origin: FragmentOrigin::Left, key: 1, data: () }));
+    assert!(alpha.contains(&Item { rectangle: Box2D { min: point2(100.0, 0.0), max: point2(150.0, 150.0) }, original: Box2D { min: point2(0.0, 0.0), max: point2(150.0, 150.0) }, origin: FragmentOrigin::Right, key: 1, data: () }));
+    assert!(alpha.contains(&Item { rectangle: Box2D { min: point2(50.0, 0.0), max: point2(100.0, 50.0) }, original: Box2D { min: point2(0.0, 0.0), max: point2(150.0, 150.0) }, origin: FragmentOrigin::Top, key: 1, data: () }));
+    assert!(alpha.contains(&Item { rectangle: Box2D { min: point2(50.0, 100.0), max: point2(100.0, 150.0) }, original: Box2D { min: point2(0.0, 0.0), max: point2(150.0, 150.0) }, origin: FragmentOrigin::Bottom, key: 1, data: () }));
+
+    assert_eq!(AxisAlignedTransform::new(&Transform2D::new(0.0, 1.0, -1.0, 0.0, 0.0, 0.0)), Ok(AxisAlignedTransform::rotation(1)));
+    assert_eq!(AxisAlignedTransform::new(&Transform2D::new(1.0, 1.0, 0.0, 1.0, 0.0, 0.0)), Err(NonAxisAlignedTransform));
+}
+
+#[test]
+fn test_batch() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+
+    let mut visibility = vec![true; 8];
+    builder.test_batch(
+        &[
+            Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) },
+            Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) },
+            Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) },
+        ],
+        &mut visibility,
+    );
+
+    assert_eq!(visibility, vec![false, true, true]);
+}
+
+#[test]
+fn occluder_set() {
+    let mut occluders = OccluderSet::new();
+    occluders.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) });
+    occluders.add(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) });
+
+    assert!(occluders.test(&Box2D { min: point2(10.0, 10.0), max: point2(140.0, 90.0) }));
+    assert!(!occluders.test(&Box2D { min: point2(60.0, 60.0), max: point2(140.0, 140.0) }));
+
+    let mut fragments = Vec::new();
+    assert!(occluders.cull_into(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, &mut fragments));
+    assert_eq!(fragments, occluders.cull(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }));
+    assert_eq!(fragments, vec![
+        Box2D { min: point2(150.0, 50.0), max: point2(200.0, 100.0) },
+        Box2D { min: point2(100.0, 0.0), max: point2(200.0, 50.0) },
+    ]);
+}
+
+#[test]
+fn empty_occluders() {
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+    let occluders = OccluderSet::from(vec![
+        // Zero width, through the middle of the rect.
+        Box2D { min: point2(50.0, 0.0), max: point2(50.0, 100.0) },
+        // Inverted, splitting around it would produce overlapping fragments.
+        Box2D { min: point2(60.0, 20.0), max: point2(40.0, 80.0) },
+    ]);
+
+    assert_eq!(occluders.cull(&rect), vec![rect]);
+}
+
+#[test]
+fn sort_opaque_items_spatially() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 150.0) }, true, 1);
+    builder.sort_opaque_items_spatially();
+
+    let mins: Vec<_> = builder.opaque_items().iter().map(|item| item.rectangle.min).collect();
+    assert_eq!(mins, vec![
+        point2(0.0, 0.0),
+        point2(0.0, 50.0),
+        point2(50.0, 50.0),
+        point2(100.0, 50.0),
+        point2(0.0, 100.0),
+    ]);
+}
+
+#[test]
+fn candidate_occluder_count() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 1);
+
+    assert_eq!(builder.candidate_occluder_count(&Box2D { min: point2(50.0, 50.0), max: point2(250.0, 150.0) }), 2);
+    assert_eq!(builder.candidate_occluder_count(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }), 1);
+    // Touching an edge doesn't count.
+    let between = Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) };
+    assert_eq!(builder.candidate_occluder_count(&between), 0);
+
+    // The dilated occluders split the rect.
+    builder.set_occluder_dilation(1.0);
+    assert_eq!(builder.candidate_occluder_count(&between), 2);
+    builder.set_occluder_dilation(0.0);
+
+    // The parts outside of the clip aren't tested.
+    builder.push_clip(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 150.0) });
+    assert_eq!(builder.candidate_occluder_count(&Box2D { min: point2(50.0, 50.0), max: point2(250.0, 150.0) }), 1);
+    builder.pop_clip();
+
+    // Overlaps within the epsilon don't split the rect.
+    struct MilliEpsilon;
+    impl Epsilon for MilliEpsilon {
+        const EPS: f32 = 0.001;
+    }
+    let mut builder: FrontToBackBuilder<u64, MilliEpsilon> = FrontToBackBuilder::with_epsilon();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    assert_eq!(builder.candidate_occluder_count(&Box2D { min: point2(99.9995, 0.0), max: point2(150.0, 100.0) }), 0);
+    assert_eq!(builder.candidate_occluder_count(&Box2D { min: point2(99.0, 0.0), max: point2(150.0, 100.0) }), 1);
+}
+
+#[test]
+fn test_min_area() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+
+    // 10x100 visible.
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(110.0, 100.0) };
+    assert!(builder.test(&rect));
+    assert!(builder.test_min_area(&rect, 1000.0));
+    assert!(!builder.test_min_area(&rect, 1001.0));
+}
+
+#[test]
+fn visible_fraction() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) }, false, 1);
+
+    assert_eq!(builder.visible_fraction(&Box2D { min: point2(50.0, 0.0), max: point2(250.0, 100.0) }), 0.75);
+    assert_eq!(builder.visible_fraction(&Box2D { min: point2(10.0, 10.0), max: point2(20.0, 20.0) }), 0.0);
+    assert_eq!(builder.visible_fraction(&Box2D { min: point2(0.0, 200.0), max: point2(10.0, 210.0) }), 1.0);
+    assert_eq!(builder.visible_fraction(&Box2D { min: point2(0.0, 200.0), max: point2(0.0, 210.0) }), 0.0);
+}
+
+#[test]
+fn occluder_contains_rect() {
+    let occluder = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+
+    // Strictly larger on all sides.
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&occluder, true, 0);
+    assert!(!builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) }, false, 1));
+    assert!(builder.alpha_items().is_empty());
+
+    // Equal.
+    assert!(!builder.add(&occluder, false, 1));
+    assert!(!builder.add(&occluder, true, 1));
+    assert!(builder.alpha_items().is_empty());
+    assert_eq!(builder.opaque_items().len(), 1);
+
+    // Larger on three sides, the bottom edges coincide.
+    assert!(!builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(90.0, 100.0) }, false, 1));
+    assert!(builder.alpha_items().is_empty());
+
+    // Larger on three sides, the rect extends past the bottom edge.
+    assert!(builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(90.0, 150.0) }, false, 1));
+    assert_eq!(builder.alpha_items(), &[
+        Item { rectangle: Box2D { min: point2(10.0, 100.0), max: point2(90.0, 150.0) }, original: Box2D { min: point2(10.0, 10.0), max: point2(90.0, 150.0) }, origin: FragmentOrigin::Bottom, key: 1, data: () },
+    ]);
+}
+
+#[test]
+fn no_keys() {
+    let mut builder: FrontToBackBuilder<()> = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, ());
+    builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, ());
+
+    assert!(std::mem::size_of::<Item<()>>() < std::mem::size_of::<Item<u64>>());
+    assert_eq!(builder.opaque_items().len(), 1);
+    assert_eq!(builder.alpha_items().len(), 2);
+
+    assert!(builder.dump_as_svg_string().starts_with("<svg"));
+}
+
+#[test]
+fn opaque_non_occluding() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add_with_kind(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, ItemKind::OpaqueNonOccluding, 0);
+    builder.add(&Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) }, true, 1);
+
+    assert_eq!(builder.opaque_items(), &[
+        Item { rectangle: Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, original: Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, origin: FragmentOrigin::Unsplit, key: 0, data: () },
+        Item { rectangle: Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) }, original: Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) }, origin: FragmentOrigin::Unsplit, key: 1, data: () },
+    ]);
+    assert_eq!(builder.stored_opaque_count(), 2);
+    assert_eq!(builder.active_occluder_count(), 1);
+    assert!(!builder.test(&Box2D { min: point2(60.0, 0.0), max: point2(100.0, 100.0) }));
+    assert!(builder.test(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 100.0) }));
+}
+
+#[test]
+fn clip_results_multi() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, false, 1);
+    builder.add(&Box2D { min: point2(300.0, 0.0), max: point2(400.0, 100.0) }, false, 2);
+
+    builder.clip_results_multi(&[
+        Box2D { min: point2(50.0, 0.0), max: point2(120.0, 50.0) },
+        Box2D { min: point2(150.0, 0.0), max: point2(250.0, 50.0) },
+    ]);
+
+    assert_eq!(builder.opaque_items(), &[
+        Item { rectangle: Box2D { min: point2(50.0, 0.0), max: point2(100.0, 50.0) }, original: Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, origin: FragmentOrigin::Unsplit, key: 0, data: () },
+    ]);
+    assert_eq!(builder.alpha_items(), &[
+        Item { rectangle: Box2D { min: point2(100.0, 0.0), max: point2(120.0, 50.0) }, original: Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, origin: FragmentOrigin::Right, key: 1, data: () },
+        Item { rectangle: Box2D { min: point2(150.0, 0.0), max: point2(200.0, 50.0) }, original: Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, origin: FragmentOrigin::Right, key: 1, data: () },
+    ]);
+}
+
+#[test]
+fn coalesce_opaque() {
+    let mut builder = FrontToBackBuilder::new();
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+    builder.add(&rect, true, 0);
+    builder.add(&Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) }, true, 0);
+    builder.clip_results_multi(&[
+        Box2D { min: point2(0.0, 0.0), max: point2(200.0, 25.0) },
+        Box2D { min: point2(0.0, 25.0), max: point2(200.0, 50.0) },
+        Box2D { min: point2(0.0, 50.0), max: point2(200.0, 75.0) },
+        Box2D { min: point2(0.0, 75.0), max: point2(200.0, 100.0) },
+    ]);
+    assert_eq!(builder.opaque_items().len(), 8);
+
+    builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 1);
+
+    builder.coalesce_opaque();
+
+    // Items from different rectangles are merged if they have the same key.
+    let both = Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) };
+    assert_eq!(builder.opaque_items().len(), 2);
+    assert_eq!(builder.opaque_items()[0].rectangle, both);
+    assert_eq!(builder.opaque_items()[0].original, both);
+    assert_eq!(builder.opaque_items()[0].origin, FragmentOrigin::Unsplit);
+    assert_eq!(builder.opaque_items()[1].key, 1);
+
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(40.0, 40.0), max: point2(60.0, 60.0) }, false, 0);
+    builder.add_with_kind(&Box2D { min: point2(0.0, 40.0), max: point2(40.0, 60.0) }, ItemKind::OccluderOnly, 1);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 2);
+    builder.coalesce_opaque();
+
+    // The top band and the right part don't share a full edge.
+    assert_eq!(builder.opaque_items().len(), 3);
+}
+
+#[test]
+fn identical_opaque_rects() {
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+
+    let mut builder = FrontToBackBuilder::new();
+    assert!(builder.add(&rect, true, 0));
+    assert!(!builder.add(&rect, true, 1));
+    assert!(!builder.add(&rect, true, 2));
+    assert_eq!(builder.opaque_items(), &[Item { rectangle: rect, original: rect, origin: FragmentOrigin::Unsplit, key: 0, data: () }]);
+
+    // With the back-to-front builder the last one is the front-most.
+    let mut builder = BackToFrontBuilder::new();
+    builder.add(&rect, true, 0);
+    builder.add(&rect, true, 1);
+    builder.add(&rect, true, 2);
+    builder.build();
+    assert_eq!(builder.opaque_items(), &[Item { rectangle: rect, original: rect, origin: FragmentOrigin::Unsplit, key: 2, data: () }]);
+}
+
+#[test]
+fn uncovered_region() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 100.0), max: point2(100.0, 200.0) }, false, 1);
+
+    let mut holes = Vec::new();
+    builder.uncovered_region(Box2D { min: point2(0.0, 0.0), max: point2(200.0, 200.0) }, &mut holes);
+
+    assert_eq!(holes, vec![
+        Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) },
+        Box2D { min: point2(100.0, 100.0), max: point2(200.0, 200.0) },
+    ]);
+}
+
+#[test]
+fn large_areas() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(1e20, 1e20) }, true, 0);
+
+    let rect = Box2D { min: point2(-1e20, 0.0), max: point2(1e20, 1e20) };
+    let visible = builder.visible_area(&rect);
+    assert!(visible.is_finite());
+    assert!((visible - 1e40).abs() < 1e34);
+    assert!(rect.area().is_infinite());
+}
+
+#[test]
+fn add_with_policy() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+
+    let policy = |coverage: f32| if coverage < 0.3 { ItemKind::Alpha } else { ItemKind::Opaque };
+
+    // 20% visible.
+    assert!(builder.add_with_policy(&Box2D { min: point2(20.0, 0.0), max: point2(120.0, 25.0) }, 1, |coverage| {
+        assert_eq!(coverage, 0.2);
+        policy(coverage)
+    }));
+    // 50% visible.
+    assert!(builder.add_with_policy(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 100.0) }, 2, policy));
+    // Not visible.
+    assert!(!builder.add_with_policy(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, 3, |coverage| {
+        assert_eq!(coverage, 0.0);
+        ItemKind::Opaque
+    }));
+
+    assert_eq!(builder.alpha_items().len(), 1);
+    assert_eq!(builder.alpha_items()[0].key, 1);
+    assert_eq!(builder.opaque_items().len(), 2);
+    assert_eq!(builder.opaque_items()[1].key, 2);
+}
+
+#[test]
+fn occluded_area() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+
+    assert_eq!(builder.occluded_area(&Box2D { min: point2(50.0, 0.0), max: point2(150.0, 10.0) }), 500.0);
+    assert_eq!(builder.occluded_area(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 10.0) }), 0.0);
+    assert_eq!(builder.occluded_area(&Box2D { min: point2(10.0, 10.0), max: point2(20.0, 20.0) }), 100.0);
+}
+
+#[test]
+fn cull_against() {
+    let mut builder = FrontToBackBuilder::new();
+
+    let occluders = vec![
+        Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) },
+        Box2D { min: point2(50.0, 0.0), max: point2(200.0, 100.0) },
+    ];
+    let items = vec![
+        (Box2D { min: point2(10.0, 10.0), max: point2(190.0, 90.0) }, 0),
+        (Box2D { min: point2(150.0, 50.0), max: point2(250.0, 150.0) }, 1),
+    ];
+
+    assert_eq!(builder.cull_against(occluders.into_iter(), items.into_iter()), 1);
+    assert!(builder.opaque_items().is_empty());
+    assert_eq!(builder.active_occluder_count(), 2);
+    assert_eq!(builder.alpha_items().len(), 2);
+    assert!(builder.alpha_items().iter().all(|item| item.key == 1));
+}
+
+#[test]
+fn build_steps() {
+    let mut reference = BackToFrontBuilder::new();
+    let mut stepped = BackToFrontBuilder::new();
+    for builder in &mut [&mut reference, &mut stepped] {
+        builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 200.0) }, false, 0);
+        builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, true, 1);
+        builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 2);
+        builder.add(&Box2D { min: point2(100.0, 100.0), max: point2(200.0, 200.0) }, true, 3);
+        builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(20.0, 20.0) }, true, 4);
+    }
+
+    reference.build();
+
+    assert!(!stepped.build_steps(2));
+    assert!(!stepped.build_steps(2));
+    assert!(stepped.build_steps(2));
+
+    assert_eq!(stepped.opaque_items(), reference.opaque_items());
+    assert_eq!(stepped.alpha_items(), reference.alpha_items());
+}
+
+#[test]
+fn flat_output() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 100.0) }, false, 1);
+
+    let mut coords = Vec::new();
+    let mut keys = Vec::new();
+    write_flat(builder.opaque_items(), &mut coords, &mut keys);
+    write_flat(builder.alpha_items(), &mut coords, &mut keys);
+
+    assert_eq!(coords, vec![0.0, 0.0, 100.0, 100.0, 100.0, 0.0, 150.0, 100.0]);
+    assert_eq!(keys, vec![0, 1]);
+}
+
+#[test]
+fn effective_visible_fraction() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add_with_opacity(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, 1.0, 0);
+    builder.add_with_opacity(&Box2D { min: point2(0.0, 100.0), max: point2(100.0, 200.0) }, 0.5, 1);
+    builder.add_with_opacity(&Box2D { min: point2(50.0, 150.0), max: point2(150.0, 250.0) }, 0.5, 2);
+    builder.add(&Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) }, false, 3);
+
+    assert_eq!(builder.opaque_items().len(), 1);
+    // Half is hidden, 3/8 is behind one layer and 1/8 behind two layers.
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 200.0) };
+    assert_eq!(builder.effective_visible_fraction(&rect), 0.375 * 0.5 + 0.125 * 0.25);
+    // Alpha items without opacity don't count.
+    let rect = Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) };
+    assert_eq!(builder.effective_visible_fraction(&rect), 1.0);
+}
+
+#[test]
+fn sort_fragments() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_sort_fragments(true);
+
+    builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 10.0) }, false, 1);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 150.0) }, false, 2);
+
+    let mins: Vec<_> = builder.alpha_items().iter().map(|item| item.rectangle.min).collect();
+    assert_eq!(mins, vec![
+        point2(200.0, 0.0),
+        point2(0.0, 0.0),
+        point2(0.0, 50.0),
+        point2(100.0, 50.0),
+        point2(0.0, 100.0),
+    ]);
+}
+
+#[test]
+fn item_budget() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_item_budget(4);
+
+    assert_eq!(builder.try_add(&Box2D { min: point2(50.0, 50.0), max: point2(100.0, 100.0) }, true, 0), Ok(true));
+    assert_eq!(builder.try_add(&Box2D { min: point2(50.0, 50.0), max: point2(100.0, 100.0) }, true, 0), Ok(false));
+    assert_eq!(builder.try_add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 150.0) }, false, 1), Err(CapacityExceeded));
+    assert!(builder.alpha_items().is_empty());
+    assert_eq!(builder.try_add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 100.0) }, false, 2), Ok(true));
+    assert_eq!(builder.alpha_items().len(), 3);
+}
+
+#[test]
+fn draw_order() {
+    let mut builder = BackToFrontBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, false, 0);
+    builder.add(&Box2D { min: point2(20.0, 0.0), max: point2(30.0, 10.0) }, true, 1);
+    builder.add(&Box2D { min: point2(40.0, 0.0), max: point2(50.0, 10.0) }, false, 2);
+    builder.build();
+
+    let order: Vec<(u64, bool)> = builder.draw_order().map(|(item, opaque)| (item.key, opaque)).collect();
+    assert_eq!(order, vec![(1, true), (0, false), (2, false)]);
+}
+
+#[test]
+fn pop() {
+    let mut builder = BackToFrontBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, false, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 1);
+    assert_eq!(builder.pop(), Some((Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 1)));
+    builder.build();
+
+    assert!(builder.opaque_items().is_empty());
+    assert_eq!(builder.alpha_items().len(), 1);
+    assert_eq!(builder.pop(), None);
+}
+
+#[test]
+fn max_occluders() {
+    let small = Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) };
+    let large = Box2D { min: point2(20.0, 0.0), max: point2(120.0, 100.0) };
+    let behind = Box2D { min: point2(30.0, 10.0), max: point2(40.0, 20.0) };
+
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_max_occluders(1);
+    builder.add(&small, true, 0);
+    builder.add(&large, true, 1);
+    assert_eq!(builder.stored_opaque_count(), 2);
+    assert_eq!(builder.active_occluder_count(), 1);
+    assert!(builder.test(&behind));
+
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_max_occluders(1);
+    builder.set_occluder_policy(OccluderPolicy::LargestArea);
+    builder.add(&small, true, 0);
+    builder.add(&large, true, 1);
+    assert_eq!(builder.stored_opaque_count(), 2);
+    assert_eq!(builder.active_occluder_count(), 1);
+    assert!(!builder.test(&behind));
+    assert!(builder.test(&small));
+}
+
+#[test]
+fn retain_commands() {
+    let mut builder = BackToFrontBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 0);
+    assert_eq!(builder.commands(), &[(Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 0)]);
+    builder.build();
+    assert!(builder.commands().is_empty());
+
+    builder.set_retain_commands(true);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 0);
+    builder.build();
+    assert_eq!(builder.commands().len(), 1);
+    builder.build();
+    assert_eq!(builder.opaque_items().len(), 1);
+}
+
+#[test]
+fn set_clip() {
+    let mut builder = FrontToBackBuilder::new();
+    let viewport = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+    builder.set_clip(Some(viewport)).unwrap();
+
+    // Queries agree with `add` about the parts outside of the clip.
+    let outside = Box2D { min: point2(200.0, 200.0), max: point2(300.0, 300.0) };
+    assert!(!builder.test(&outside));
+    assert_eq!(builder.visible_fraction(&outside), 0.0);
+    assert_eq!(builder.occluded_area(&outside), 0.0);
+    assert!(!builder.test_min_area(&outside, 1.0));
+    assert_eq!(builder.visible_fraction(&Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) }), 0.5);
+    assert_eq!(builder.test(&outside), builder.add(&outside, false, 3));
+
+    let rect = Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) };
+    assert!(builder.add(&rect, true, 0));
+    assert!(!builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 1));
+    assert_eq!(builder.opaque_items()[0].rectangle, Box2D { min: point2(50.0, 0.0), max: point2(100.0, 100.0) });
+    assert_eq!(builder.opaque_items()[0].original, rect);
+    assert_eq!(builder.opaque_items().len(), 1);
+
+    builder.push_clip(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 200.0) });
+    builder.add(&Box2D { min: point2(0.0, 50.0), max: point2(200.0, 150.0) }, false, 2);
+    assert_eq!(builder.alpha_items()[0].rectangle, Box2D { min: point2(0.0, 50.0), max: point2(50.0, 100.0) });
+
+    builder.clear();
+    assert!(!builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 1));
+
+    // Invalid clips are refused and the previous one is kept.
+    let inverted = Box2D { min: point2(100.0, 0.0), max: point2(0.0, 100.0) };
+    assert_eq!(builder.set_clip(Some(inverted)), Err(BuilderError::InvertedClip));
+    let empty = Box2D { min: point2(0.0, 0.0), max: point2(0.0, 100.0) };
+    assert_eq!(builder.set_clip(Some(empty)), Err(BuilderError::EmptyClip));
+    assert!(!builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 1));
+    assert!(builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 2));
+
+    builder.set_clip(None).unwrap();
+    assert!(builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 1));
+}
+
+#[test]
+fn with_clip() {
+    let viewport = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+    let mut builder: FrontToBackBuilder = FrontToBackBuilder::with_clip(viewport).unwrap();
+    assert!(builder.add(&Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) }, true, 0));
+    assert_eq!(builder.opaque_items()[0].rectangle, Box2D { min: point2(50.0, 0.0), max: point2(100.0, 100.0) });
+
+    let with_clip = |clip| FrontToBackBuilder::<u64>::with_clip(clip).err();
+    assert_eq!(with_clip(Box2D { min: point2(100.0, 0.0), max: point2(0.0, 100.0) }), Some(BuilderError::InvertedClip));
+    assert_eq!(with_clip(Box2D { min: point2(0.0, 0.0), max: point2(100.0, 0.0) }), Some(BuilderError::EmptyClip));
+    assert_eq!(with_clip(Box2D { min: point2(0.0, 0.0), max: point2(f32::INFINITY, 100.0) }), Some(BuilderError::NonFiniteClip));
+    assert_eq!(with_clip(Box2D { min: point2(f32::NAN, 0.0), max: point2(100.0, 100.0) }), Some(BuilderError::NonFiniteClip));
+}
+
+#[test]
+fn rebuild() {
+    let mut builder = FrontToBackBuilder::retained();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, false, 1);
+    builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(20.0, 20.0) }, true, 2);
+    assert_eq!(builder.commands().len(), 3);
+    assert_eq!(builder.alpha_items().len(), 1);
+
+    builder.commands_mut().remove(0);
+    assert_eq!(builder.rebuild(), 2);
+    assert_eq!(builder.commands().len(), 2);
+    assert_eq!(builder.alpha_items()[0].rectangle, Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) });
+    assert_eq!(builder.opaque_items().len(), 1);
+
+    builder.clear();
+    assert!(builder.commands().is_empty());
+
+    // Methods that the commands can't represent aren't recorded.
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+    builder.add_with_opacity(&rect, 0.5, 0);
+    builder.add_with_opacity(&rect, 1.0, 1);
+    builder.cull_against(std::iter::once(rect), std::iter::once((rect, 2)));
+    builder.add_with_kind(&rect, ItemKind::Alpha, 3);
+    assert!(builder.commands().is_empty());
+    builder.add_with_depth_check(&rect, false, 4, Some(1.0));
+    builder.extend(std::iter::once((rect, false, 5)));
+    assert_eq!(builder.commands(), &[(rect, false, 4), (rect, false, 5)]);
+
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    assert!(builder.commands().is_empty());
+}
+
+#[test]
+fn occluder_dilation() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_occluder_dilation(0.5);
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    assert!(!builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.25, 100.0) }, false, 1));
+    assert!(builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(101.0, 100.0) }, false, 2));
+
+    assert_eq!(builder.opaque_items()[0].rectangle, Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) });
+    assert_eq!(builder.alpha_items()[0].rectangle, Box2D { min: point2(100.5, 0.0), max: point2(101.0, 100.0) });
+}
+
+#[test]
+fn culler() {
+    let mut culler = Culler::new();
+    let mut visible = Vec::new();
+
+    assert!(culler.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, |r| visible.push(*r)));
+    assert!(culler.add(&Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) }, false, |r| visible.push(*r)));
+    assert!(!culler.add(&Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) }, true, |r| visible.push(*r)));
+
+    assert_eq!(visible, vec![
+        Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) },
+        Box2D { min: point2(100.0, 0.0), max: point2(150.0, 100.0) },
+    ]);
+    assert_eq!(culler.occluders().occluders().len(), 1);
+}
+
+#[test]
+fn visible_coverage_of_key_in_region() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 1);
+
+    let region = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 50.0) };
+    assert_eq!(builder.visible_coverage_of_key_in_region(0, &region), 0.5);
+    assert_eq!(builder.visible_coverage_of_key_in_region(1, &region), 0.5);
+    assert_eq!(builder.visible_coverage_of_key_in_region(2, &region), 0.0);
+    assert_eq!(builder.visible_coverage_of_key_in_region(0, &Box2D::zero()), 0.0);
+}
+
+#[test]
+fn clear_and_reserve() {
+    let mut builder: FrontToBackBuilder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 0);
+    builder.clear_and_reserve(32, 64);
+
+    assert!(builder.opaque_items().is_empty());
+    assert_eq!(builder.active_occluder_count(), 0);
+    assert!(builder.opaque_items.capacity() >= 32);
+    assert!(builder.alpha_items.capacity() >= 64);
+}
+
+#[test]
+fn occluder_only() {
+    let mut builder = FrontToBackBuilder::new();
+
+    assert!(builder.add_with_kind(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, ItemKind::OccluderOnly, 0));
+    assert!(!builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) }, true, 1));
+
+    assert!(builder.opaque_items().is_empty());
+    assert!(builder.alpha_items().is_empty());
+    assert_eq!(builder.active_occluder_count(), 1);
+}
+
+#[test]
+fn visible_bounds_by_key() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(40.0, 0.0), max: point2(60.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 50.0) }, false, 1);
+    builder.add(&Box2D { min: point2(45.0, 10.0), max: point2(55.0, 20.0) }, false, 2);
+
+    let bounds = builder.visible_bounds_by_key();
+    assert_eq!(bounds.len(), 2);
+    assert_eq!(bounds[&0], Box2D { min: point2(40.0, 0.0), max: point2(60.0, 100.0) });
+    assert_eq!(bounds[&1], Box2D { min: point2(0.0, 0.0), max: point2(100.0, 50.0) });
+}
+
+#[test]
+fn merge() {
+    let mut a = FrontToBackBuilder::new();
+    a.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    a.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, false, 1);
+
+    let mut b = FrontToBackBuilder::new();
+    b.add(&Box2D { min: point2(50.0, 0.0), max: point2(250.0, 100.0) }, true, 2);
+    b.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 200.0) }, false, 3);
+
+    a.merge(&b);
+
+    assert_eq!(a.opaque_items().len(), 2);
+    assert_eq!(a.opaque_items()[1].rectangle, Box2D { min: point2(100.0, 0.0), max: point2(250.0, 100.0) });
+    assert_eq!(a.opaque_items()[1].original, Box2D { min: point2(50.0, 0.0), max: point2(250.0, 100.0) });
+    assert_eq!(a.opaque_items()[1].origin, FragmentOrigin::Right);
+
+    assert_eq!(a.alpha_items().len(), 2);
+    assert_eq!(a.alpha_items()[0].rectangle, Box2D { min: point2(250.0, 0.0), max: point2(300.0, 100.0) });
+    assert_eq!(a.alpha_items()[0].key, 1);
+    assert_eq!(a.alpha_items()[1].rectangle, Box2D { min: point2(0.0, 100.0), max: point2(100.0, 200.0) });
+    assert_eq!(a.alpha_items()[1].key, 3);
+
+    assert!(!a.test(&Box2D { min: point2(0.0, 0.0), max: point2(250.0, 100.0) }));
+    assert_eq!(a.active_occluder_count(), 2);
+}
+
+#[test]
+fn triangles() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 100.0) }, false, 1);
+
+    let mut vertices = Vec::new();
+    write_triangles(builder.alpha_items(), &mut vertices);
+    assert_eq!(vertices, vec![
+        [100.0, 0.0], [150.0, 0.0], [150.0, 100.0],
+        [100.0, 0.0], [150.0, 100.0], [100.0, 100.0],
+    ]);
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    write_indexed_triangles(builder.opaque_items(), &mut vertices, &mut indices);
+    write_indexed_triangles(builder.alpha_items(), &mut vertices, &mut indices);
+    assert_eq!(vertices.len(), 8);
+    assert_eq!(vertices[4], [100.0, 0.0]);
+    assert_eq!(indices, vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7]);
+}
+
+#[test]
+fn coincident_edges() {
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+
+    // Each bit selects whether the corresponding edge of the occluder coincides with the
+    // rectangle's edge or is strictly inside of it.
+    for mask in 0..16 {
+        let occluder = Box2D {
+            min: point2(
+                if mask & 1 != 0 { 0.0 } else { 20.0 },
+                if mask & 2 != 0 { 0.0 } else { 30.0 },
+            ),
+            max: point2(
+                if mask & 4 != 0 { 100.0 } else { 70.0 },
+                if mask & 8 != 0 { 100.0 } else { 80.0 },
+            ),
+        };
+
+        let mut fragments: SmallVec<[Box2D<f32>; INLINE_FRAGMENT_CAPACITY]> = SmallVec::new();
+        fragments.push(rect);
+        apply_occluder(&occluder, &mut fragments);
+
+        let expected_count = 4 - (mask as u32).count_ones() as usize;
+        assert_eq!(fragments.len(), expected_count, "mask {}", mask);
+
+        let mut total = 0.0;
+        for fragment in &fragments {
+            assert!(!fragment.is_empty(), "mask {}: {:?}", mask, fragment);
+            assert!(!fragment.intersects(&occluder), "mask {}: {:?}", mask, fragment);
+            total += area(fragment);
+        }
+        assert_eq!(total, area(&rect) - area(&occluder), "mask {}", mask);
+    }
+}
+
+#[test]
+fn round_output() {
+    let build = || {
+        let mut builder = FrontToBackBuilder::new();
+        builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.4, 10.0) }, true, 0);
+        builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(20.6, 10.0) }, true, 1);
+        builder.add(&Box2D { min: point2(20.6, 0.0), max: point2(20.9, 10.0) }, true, 2);
+        builder
+    };
+
+    let mut builder = build();
+    builder.round_output(RoundMode::Floor);
+    assert_eq!(builder.opaque_items().len(), 2);
+    assert_eq!(builder.opaque_items()[0].rectangle.max.x, builder.opaque_items()[1].rectangle.min.x);
+    assert_eq!(builder.opaque_items()[1].rectangle.max.x, 20.0);
+
+    let mut builder = build();
+    builder.round_output(RoundMode::Nearest);
+    assert_eq!(builder.opaque_items()[0].rectangle.max.x, 10.0);
+    assert_eq!(builder.opaque_items()[1].rectangle, Box2D { min: point2(10.0, 0.0), max: point2(21.0, 10.0) });
+    assert_eq!(builder.opaque_items().len(), 2);
+
+    let mut builder = build();
+    builder.round_output(RoundMode::Conservative);
+    assert_eq!(builder.opaque_items()[0].rectangle.max.x, 11.0);
+    assert_eq!(builder.opaque_items()[1].rectangle, Box2D { min: point2(10.0, 0.0), max: point2(21.0, 10.0) });
+    assert_eq!(builder.opaque_items()[2].rectangle, Box2D { min: point2(20.0, 0.0), max: point2(21.0, 10.0) });
+}
+
+#[test]
+fn trace_output() {
+    use std::sync::{Arc, Mutex};
+
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+    impl std::io::Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_trace_output(Some(Box::new(SharedOutput(output.clone()))));
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 100.0) }, false, 1);
+    builder.set_trace_output(None);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 100.0) }, false, 2);
+
+    let trace = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("add Opaque"));
+    assert!(lines[0].ends_with("0 occluders, 1 fragments"));
+    assert!(lines[2].starts_with("add Alpha"));
+    assert!(lines[2].ends_with("1 occluders, 1 fragments"));
+    assert!(lines[3].trim_start().starts_with("Right"));
+
+    // Occluders are counted with the dilation applied.
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_occluder_dilation(10.0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.set_trace_output(Some(Box::new(SharedOutput(output.clone()))));
+    builder.add(&Box2D { min: point2(105.0, 0.0), max: point2(150.0, 100.0) }, false, 1);
+
+    let trace = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+    assert!(trace.lines().next().unwrap().ends_with("1 occluders, 1 fragments"));
+
+    fn assert_sync<T: Sync>(_: &T) {}
+    assert_sync(&builder);
+}
+
+#[test]
+fn previous_occluders() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    let previous = builder.occluder_set().clone();
+
+    builder.clear();
+    builder.add(&Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) }, true, 0);
+
+    // Visible in the current frame but hidden in the previous one.
+    let rect = Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) };
+    assert!(builder.test(&rect) && !previous.test(&rect));
+    assert!(!test_occluders(builder.occluder_set().occluders(), &Box2D { min: point2(110.0, 10.0), max: point2(190.0, 90.0) }));
+
+    let set = OccluderSet::from(vec![Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }]);
+    assert!(!set.test(&Box2D { min: point2(1.0, 1.0), max: point2(9.0, 9.0) }));
+}
+
+#[test]
+fn alpha_union() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 0);
+    builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, 1);
+    builder.add(&Box2D { min: point2(10.0, 10.0), max: point2(20.0, 20.0) }, false, 2);
+
+    let mut union = Vec::new();
+    builder.alpha_union(&mut union);
+
+    assert_eq!(union.len(), 3);
+    let total: f64 = union.iter().map(area).sum();
+    assert_eq!(total, 10000.0 + 10000.0 - 2500.0);
+    for (i, a) in union.iter().enumerate() {
+        for b in &union[i + 1..] {
+            assert!(!a.intersects(b));
+        }
+    }
+}
+
+#[test]
+fn add_with_depth_check() {
+    let mut builder = FrontToBackBuilder::new();
+
+    assert!(builder.add_with_depth_check(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 0, Some(1.0)));
+    assert!(builder.add_with_depth_check(&Box2D { min: point2(0.0, 0.0), max: point2(20.0, 10.0) }, true, 1, None));
+    assert!(!builder.add_with_depth_check(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 2, Some(1.0)));
+    builder.clear();
+    assert!(builder.add_with_depth_check(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 3, Some(0.0)));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic]
+fn decreasing_depth() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add_with_depth_check(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 0, Some(2.0));
+    builder.add_with_depth_check(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 1, Some(1.0));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic]
+fn non_finite_rect() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(f32::INFINITY, 10.0) }, true, 0);
+}
+
+#[cfg(not(debug_assertions))]
+#[test]
+fn non_finite_rect() {
+    let mut builder = FrontToBackBuilder::new();
+
+    assert!(!builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(f32::INFINITY, 10.0) }, true, 0));
+    assert!(!builder.test(&Box2D { min: point2(f32::NAN, 0.0), max: point2(10.0, 10.0) }));
+    assert!(builder.opaque_items().is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_snapshot() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(20.0, 10.0) }, false, 1);
+
+    let json = serde_json::to_value(builder.alpha_items()[0]).unwrap();
+    assert_eq!(json, serde_json::json!({
+        "rectangle": { "min": [10.0, 0.0], "max": [20.0, 10.0] },
+        "original": { "min": [0.0, 0.0], "max": [20.0, 10.0] },
+        "origin": "Right",
+        "key": 1,
+        "data": null,
+    }));
+
+    let serialized = serde_json::to_string(&builder.snapshot()).unwrap();
+    let snapshot: Snapshot = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(snapshot.opaque_items, builder.opaque_items());
+    assert_eq!(snapshot.alpha_items, builder.alpha_items());
+}
+
+#[test]
+fn dump_as_svg_with_originals() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, 1);
+
+    let mut output = Vec::new();
+    builder.dump_as_svg_with_originals(&mut output).unwrap();
+    let svg = String::from_utf8(output).unwrap();
+
+    assert_eq!(svg.matches("stroke-dasharray").count(), 1);
+    assert_eq!(svg.matches("fill-opacity:0;").count(), 2);
+    assert_eq!(svg.matches("<rect").count(), 5);
+    assert!(!builder.dump_as_svg_string().contains("stroke-dasharray"));
+}
+
+#[test]
+fn degenerate_rects() {
+    let mut builder = FrontToBackBuilder::new();
+    let empty = Box2D { min: point2(10.0, 0.0), max: point2(10.0, 100.0) };
+    let inverted = Box2D { min: point2(100.0, 100.0), max: point2(0.0, 0.0) };
+
+    assert!(!builder.test(&empty));
+    assert!(!builder.test(&inverted));
+    assert!(!builder.add(&empty, true, 0));
+    assert!(!builder.add(&inverted, false, 1));
+    assert!(!builder.add_unsplittable(&inverted, true, 2));
+    assert!(builder.opaque_items().is_empty());
+    assert!(builder.alpha_items().is_empty());
+    assert_eq!(builder.cull_efficiency(), 0.0);
+
+    // An empty rect on the edge of an occluder is treated the same as anywhere else.
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 100.0) }, true, 3);
+    assert!(!builder.test(&empty));
+}
+
+#[test]
+fn contained_in_occluder() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 1);
+
+    let rect = Box2D { min: point2(5.0, 5.0), max: point2(100.0, 50.0) };
+    assert!(!builder.test(&rect));
+    assert!(!builder.add(&rect, false, 2));
+    assert!(!builder.add(&rect, true, 3));
+    assert_eq!(builder.opaque_items().len(), 3);
+    assert!(builder.alpha_items().is_empty());
+}
+
+#[test]
+fn occluder_at() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) }, true, 1);
+    builder.add(&Box2D { min: point2(0.0, 100.0), max: point2(100.0, 200.0) }, false, 2);
+
+    assert_eq!(builder.occluder_at(point2(50.0, 50.0)).map(|item| item.key), Some(0));
+    assert_eq!(builder.occluder_at(point2(100.0, 0.0)).map(|item| item.key), Some(1));
+    assert!(builder.occluder_at(point2(200.0, 50.0)).is_none());
+    assert!(builder.is_point_occluded(point2(0.0, 0.0)));
+    assert!(!builder.is_point_occluded(point2(50.0, 150.0)));
+
+    // Unsplittable rectangles overlap the items in front of them.
+    builder.add_unsplittable(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, true, 3);
+    assert_eq!(builder.occluder_at(point2(75.0, 75.0)).map(|item| item.key), Some(0));
+    assert_eq!(builder.occluder_at(point2(75.0, 125.0)).map(|item| item.key), Some(3));
+    assert_eq!(builder.opaque_area(), 30000.0);
+}
+
+#[test]
+fn overdraw() {
+    let mut builder = FrontToBackBuilder::new();
+    assert_eq!(builder.alpha_overdraw(), 0.0);
+    assert_eq!(builder.opaque_area(), 0.0);
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, false, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 1);
+    builder.add(&Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) }, true, 2);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, false, 3);
+    builder.add(&Box2D { min: point2(140.0, 0.0), max: point2(200.0, 50.0) }, false, 4);
+
+    assert_eq!(builder.opaque_area(), 15000.0);
+    // Only the visible part of the last alpha item overlaps another alpha item.
+    assert_eq!(builder.alpha_overdraw(), 50.0 * 50.0);
+}
+
+#[test]
+fn cull_efficiency() {
+    let mut builder = FrontToBackBuilder::new();
+    assert_eq!(builder.cull_efficiency(), 0.0);
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    assert_eq!(builder.cull_efficiency(), 0.0);
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 1);
+    assert_eq!(builder.cull_efficiency(), 0.5);
+
+    builder.add_with_kind(&Box2D { min: point2(0.0, 0.0), max: point2(500.0, 500.0) }, ItemKind::OccluderOnly, 2);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, false, 3);
+    assert_eq!(builder.cull_efficiency(), 0.75);
+
+    // Fully culled unsplittable rectangles count like split ones.
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    assert!(!builder.add_unsplittable(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 1));
+    assert_eq!(builder.cull_efficiency(), 0.5);
+}
+
+#[test]
+fn add_unsplittable() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    assert!(!builder.add_unsplittable(&Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) }, false, 1));
+    assert!(builder.add_unsplittable(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, 2));
+
+    assert_eq!(builder.alpha_items(), &[
+        Item { rectangle: Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, original: Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, origin: FragmentOrigin::Unsplit, key: 2, data: () },
+    ]);
+}
+
+#[test]
+fn add_unsplittable_clipped() {
+    // The item is stored whole but only occludes inside of the clip.
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+    let mut builder = FrontToBackBuilder::new();
+    builder.push_clip(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) });
+    assert!(builder.add_unsplittable(&rect, true, 0));
+    builder.pop_clip();
+    assert_eq!(builder.opaque_items()[0].rectangle, rect);
+    assert!(!builder.add(&Box2D { min: point2(2.0, 2.0), max: point2(8.0, 8.0) }, false, 1));
+    assert!(builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(60.0, 60.0) }, false, 2));
+
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_clip(Some(Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) })).unwrap();
+    assert!(builder.add_unsplittable(&rect, true, 0));
+    builder.set_clip(None).unwrap();
+    assert!(builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(60.0, 60.0) }, false, 1));
+    assert_eq!(builder.occluder_set().occluders(), &[Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }]);
+}
+
+#[test]
+fn add_unsplittable_many() {
+    let mut builder = FrontToBackBuilder::new();
+
+    let rects = [
+        (Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0),
+        (Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) }, false, 1),
+        (Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, 2),
+    ];
+    let mut visible = [false; 3];
+
+    assert_eq!(builder.add_unsplittable_many(&rects, &mut visible), 2);
+    assert_eq!(visible, [true, false, true]);
+}
+
+#[test]
+fn inline_capacity() {
+    let mut small: FrontToBackBuilder<u64, ZeroEpsilon, (), UnknownUnit, 2> = FrontToBackBuilder::default();
+    let mut builder = FrontToBackBuilder::new();
+
+    for i in 0..5 {
+        let x = i as f32 * 20.0 + 5.0;
+        let rect = Box2D { min: point2(x, 10.0), max: point2(x + 10.0, 20.0) };
+        small.add(&rect, true, i);
+        builder.add(&rect, true, i);
+    }
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 30.0) };
+    assert!(small.add(&rect, false, 5));
+    builder.add(&rect, false, 5);
+
+    assert!(small.alpha_items().len() > 2);
+    assert_eq!(small.alpha_items(), builder.alpha_items());
+}
+
+#[test]
+fn add_with_scratch() {
+    let mut scratch = FragmentScratch::new();
+    let mut builder = FrontToBackBuilder::new();
+    let mut reference = FrontToBackBuilder::new();
+
+    for i in 0..20 {
+        let x = i as f32 * 10.0 + 2.0;
+        let rect = Box2D { min: point2(x, 10.0), max: point2(x + 5.0, 20.0) };
+        assert!(builder.add_with_scratch(&rect, true, i, &mut scratch));
+        reference.add(&rect, true, i);
+    }
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(200.0, 30.0) };
+    assert!(builder.add_with_scratch(&rect, false, 20, &mut scratch));
+    reference.add(&rect, false, 20);
+    assert!(!builder.add_with_scratch(&Box2D { min: point2(3.0, 11.0), max: point2(6.0, 19.0) }, false, 21, &mut scratch));
+
+    assert!(builder.alpha_items().len() > INLINE_FRAGMENT_CAPACITY);
+    assert_eq!(builder.opaque_items(), reference.opaque_items());
+    assert_eq!(builder.alpha_items(), reference.alpha_items());
+}
+
+#[test]
+fn remove_opaque() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 1);
+    builder.add(&Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) }, true, 2);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 50.0) }, false, 3);
+
+    assert_eq!(builder.remove_opaque(0), 1);
+    assert_eq!(builder.remove_opaque(0), 0);
+    assert_eq!(builder.opaque_items().len(), 2);
+    assert_eq!(builder.active_occluder_count(), 2);
+    // The hidden part of the rectangle behind it isn't restored.
+    assert_eq!(builder.opaque_items()[1].rectangle, Box2D { min: point2(100.0, 0.0), max: point2(150.0, 100.0) });
+    assert!(builder.test(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 100.0) }));
+    assert!(!builder.test(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }));
+
+    builder.lock();
+    assert_eq!(builder.remove_opaque(1), 1);
+    assert!(!builder.test(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }));
+}
+
+#[test]
+fn add_detailed() {
+    let mut builder = FrontToBackBuilder::new();
+
+    let result = builder.add_detailed(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 100.0) }, true, 0);
+    assert_eq!(result, AddResult { visible: true, fragment_count: 1 });
+    let result = builder.add_detailed(&Box2D { min: point2(0.0, 10.0), max: point2(5.0, 20.0) }, false, 1);
+    assert_eq!(result, AddResult { visible: false, fragment_count: 0 });
+    let result = builder.add_detailed(&Box2D { min: point2(-10.0, 0.0), max: point2(20.0, 100.0) }, true, 2);
+    assert_eq!(result, AddResult { visible: true, fragment_count: 2 });
+}
+
+#[test]
+fn add_with_fragments() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_sort_fragments(true);
+    let mut fragments = Vec::new();
+
+    assert!(builder.add_with_fragments(&Box2D { min: point2(10.0, 10.0), max: point2(20.0, 20.0) }, true, 0, &mut fragments));
+    assert!(builder.add_with_fragments(&Box2D { min: point2(0.0, 0.0), max: point2(30.0, 30.0) }, false, 1, &mut fragments));
+    assert!(!builder.add_with_fragments(&Box2D { min: point2(12.0, 12.0), max: point2(18.0, 18.0) }, false, 2, &mut fragments));
+
+    assert_eq!(fragments.len(), 5);
+    assert_eq!(fragments[0], builder.opaque_items()[0].rectangle);
+    let alpha: Vec<Box2D<f32>> = builder.alpha_items().iter().map(|item| item.rectangle).collect();
+    assert_eq!(&fragments[1..], &alpha[..]);
+}
+
+#[test]
+fn split_strategy() {
+    let occluders = [
+        Box2D { min: point2(20.0, 20.0), max: point2(40.0, 40.0) },
+        Box2D { min: point2(50.0, 0.0), max: point2(60.0, 70.0) },
+        Box2D { min: point2(10.0, 60.0), max: point2(90.0, 80.0) },
+    ];
+    let rect = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+
+    let mut areas = Vec::new();
+    for &strategy in &[SplitStrategy::HorizontalBands, SplitStrategy::VerticalBands, SplitStrategy::NinePatch] {
+        let mut builder = FrontToBackBuilder::new();
+        builder.set_split_strategy(strategy);
+        for occluder in &occluders {
+            builder.add(occluder, true, 0);
+        }
+        builder.add(&rect, false, 1);
+
+        let alpha = builder.alpha_items();
+        for (i, a) in alpha.iter().enumerate() {
+            for b in &alpha[i + 1..] {
+                assert!(!a.rectangle.intersects(&b.rectangle));
+            }
+        }
+        areas.push(alpha.iter().map(|item| area(&item.rectangle)).sum::<f64>());
+    }
+
+    assert_eq!(areas[0], 100.0 * 100.0 - 400.0 - 700.0 - 1600.0 + 100.0);
+    assert_eq!(areas[1], areas[0]);
+    assert_eq!(areas[2], areas[0]);
+
+    let mut builder = FrontToBackBuilder::new();
+    builder.set_split_strategy(SplitStrategy::NinePatch);
+    builder.add(&occluders[0], true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(60.0, 60.0) }, false, 1);
+    assert_eq!(builder.alpha_items().len(), 8);
+}
+
+#[test]
+fn extend() {
+    let mut builder = FrontToBackBuilder::new();
+
+    let rects = vec![
+        (Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0),
+        (Box2D { min: point2(10.0, 10.0), max: point2(90.0, 90.0) }, false, 1),
+        (Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, 2),
+    ];
+
+    assert_eq!(builder.extend(rects), 2);
+    assert_eq!(builder.opaque_items().len(), 1);
+    assert_eq!(builder.alpha_items().len(), 2);
+}
+
+#[test]
+fn key_and_bits() {
+    use std::collections::HashSet;
+
+    let mut previous = FrontToBackBuilder::new();
+    previous.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    previous.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 100.0) }, false, 1);
+
+    let mut current = FrontToBackBuilder::new();
+    current.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    current.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, false, 1);
+
+    let before: HashSet<_> = previous.alpha_items().iter().chain(previous.opaque_items()).map(Item::key_and_bits).collect();
+    let changed: Vec<_> = current.alpha_items().iter().chain(current.opaque_items()).filter(|item| !before.contains(&item.key_and_bits())).collect();
+
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].rectangle, Box2D { min: point2(100.0, 0.0), max: point2(200.0, 100.0) });
+
+    let mut builder: ScalarFrontToBackBuilder<i32> = FrontToBackBuilder::default();
+    builder.add(&Box2D { min: point2(-10, 0), max: point2(100, 100) }, true, 0);
+    assert_eq!(builder.opaque_items()[0].key_and_bits(), (0, [-10i32 as u64, 0, 100, 100]));
+
+    let mut builder: ScalarFrontToBackBuilder<f64> = FrontToBackBuilder::default();
+    builder.add(&Box2D { min: point2(0.5, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    assert_eq!(builder.opaque_items()[0].key_and_bits().1[0], 0.5f64.to_bits());
+}
+
+#[test]
+fn occupancy_grid() {
+    let viewport = Box2D { min: point2(0.0, 0.0), max: point2(800.0, 800.0) };
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 50.0) }, true, 0);
+    builder.set_occupancy_grid(Some(viewport));
+    builder.add(&Box2D { min: point2(750.0, 750.0), max: point2(900.0, 900.0) }, true, 1);
+
+    assert!(!builder.test(&Box2D { min: point2(10.0, 10.0), max: point2(40.0, 40.0) }));
+    assert!(!builder.test(&Box2D { min: point2(760.0, 760.0), max: point2(890.0, 890.0) }));
+    assert!(builder.test(&Box2D { min: point2(300.0, 300.0), max: point2(400.0, 400.0) }));
+
+    // The results match a builder without the grid.
+    let mut reference = FrontToBackBuilder::new();
+    reference.add(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 50.0) }, true, 0);
+    reference.add(&Box2D { min: point2(750.0, 750.0), max: point2(900.0, 900.0) }, true, 1);
+    for rect in &[
+        Box2D { min: point2(0.0, 0.0), max: point2(800.0, 100.0) },
+        Box2D { min: point2(-100.0, 30.0), max: point2(30.0, 500.0) },
+        Box2D { min: point2(200.0, 200.0), max: point2(300.0, 300.0) },
+    ] {
+        builder.add(rect, false, 2);
+        reference.add(rect, false, 2);
+    }
+    assert_eq!(builder.alpha_items(), reference.alpha_items());
+
+    assert_eq!(OccupancyGrid { viewport, mask: 0 }.cells(&viewport), u64::MAX);
+    assert_eq!(OccupancyGrid { viewport, mask: 0 }.cells(&Box2D { min: point2(-10.0, -10.0), max: point2(10.0, 10.0) }), 1);
+}
+
+#[test]
+fn zero_opacity() {
+    let mut builder = FrontToBackBuilder::new();
+
+    assert!(!builder.add_with_opacity(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, 0.0, 0));
+    assert!(builder.alpha_items().is_empty());
+    assert_eq!(builder.cull_efficiency(), 0.0);
+}
+
+#[test]
+fn build_cancellable() {
+    use std::cell::Cell;
+
+    let mut builder = BackToFrontBuilder::new();
+    for i in 0..200 {
+        let x = i as f32;
+        builder.add(&Box2D { min: point2(x, 0.0), max: point2(x + 10.0, 10.0) }, true, i);
+    }
+
+    let calls = Cell::new(0);
+    let result = builder.build_cancellable(|| {
+        calls.set(calls.get() + 1);
+        calls.get() > 2
+    });
+    assert_eq!(result, Err(Cancelled));
+    assert!(builder.opaque_items().is_empty());
+    assert_eq!(builder.commands().len(), 200);
+
+    assert_eq!(builder.build_cancellable(|| false), Ok(()));
+    assert_eq!(builder.opaque_items().len(), 200);
+    assert!(builder.commands().is_empty());
+}
+
+#[test]
+fn opaque_outline() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(20.0, 20.0) }, true, 1);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 50.0) }, false, 2);
+
+    let outline = builder.opaque_outline();
+    assert_eq!(outline.len(), 1);
+    assert_eq!(outline[0].len(), 4);
+    assert!(outline[0].contains(&point2(20.0, 20.0)));
+}
+
+#[test]
+fn occluder_priority() {
+    let mut occluders = OccluderSet::new();
+    occluders.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) });
+    occluders.add_with_priority(&Box2D { min: point2(20.0, 0.0), max: point2(30.0, 10.0) }, 1.0);
+    occluders.add_with_priority(&Box2D { min: point2(40.0, 0.0), max: point2(50.0, 10.0) }, 2.0);
+    occluders.add_with_priority(&Box2D { min: point2(60.0, 0.0), max: point2(70.0, 10.0) }, 1.0);
+
+    let order: Vec<f32> = occluders.occluders().iter().map(|o| o.min.x).collect();
+    assert_eq!(order, vec![40.0, 20.0, 60.0, 0.0]);
+    assert!(!occluders.test(&Box2D { min: point2(1.0, 1.0), max: point2(9.0, 9.0) }));
+    assert!(occluders.test(&Box2D { min: point2(0.0, 0.0), max: point2(70.0, 10.0) }));
+
+    occluders.add_with_priority(&Box2D { min: point2(80.0, 0.0), max: point2(90.0, 10.0) }, f32::NAN);
+    occluders.add_with_priority(&Box2D { min: point2(100.0, 0.0), max: point2(110.0, 10.0) }, 0.5);
+    occluders.add(&Box2D { min: point2(120.0, 0.0), max: point2(130.0, 10.0) });
+    let order: Vec<f32> = occluders.occluders().iter().map(|o| o.min.x).collect();
+    assert_eq!(order, vec![40.0, 20.0, 60.0, 100.0, 0.0, 80.0, 120.0]);
+}
+
+#[test]
+fn alpha_ordered() {
+    let mut builder = BackToFrontBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, true, 1);
+    builder.add(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, 2);
+    builder.add(&Box2D { min: point2(20.0, 20.0), max: point2(30.0, 30.0) }, false, 3);
+    builder.build_steps(2);
+    builder.build_steps(2);
+    builder.debug_assert_alpha_ordered();
+
+    builder.round_output(RoundMode::Nearest);
+    builder.debug_assert_alpha_ordered();
+
+    #[cfg(debug_assertions)]
+    assert_eq!(builder.alpha_sequence, vec![0, 0, 2, 3]);
+}
+
+#[test]
+fn wrapping_culler() {
+    let mut culler = WrappingCuller::new(euclid::size2(100.0, 100.0));
+    let mut visible = Vec::new();
+
+    assert!(culler.add(&Box2D { min: point2(90.0, 0.0), max: point2(110.0, 50.0) }, true, |_| {}));
+    assert!(!culler.add(&Box2D { min: point2(0.0, 10.0), max: point2(5.0, 40.0) }, false, |r| visible.push(*r)));
+    assert!(!culler.add(&Box2D { min: point2(195.0, 110.0), max: point2(200.0, 140.0) }, false, |r| visible.push(*r)));
+    assert!(culler.add(&Box2D { min: point2(0.0, 0.0), max: point2(20.0, 50.0) }, false, |r| visible.push(*r)));
+
+    assert_eq!(visible, vec![Box2D { min: point2(10.0, 0.0), max: point2(20.0, 50.0) }]);
+
+    // Rectangles larger than the viewport are clamped to one period.
+    let mut culler = WrappingCuller::new(euclid::size2(100.0, 100.0));
+    visible.clear();
+    assert!(culler.add(&Box2D { min: point2(-1.0e5, 0.0), max: point2(1.0e30, 1.0e30) }, true, |r| visible.push(*r)));
+    assert_eq!(visible.len(), 1);
+    assert_eq!(visible[0].size(), euclid::size2(100.0, 100.0));
+    assert!(!culler.add(&Box2D { min: point2(1.0e6, -1.0e6), max: point2(2.0e6, 1.0e6) }, false, |_| {}));
+    assert!(!culler.add(&Box2D { min: point2(0.0, 0.0), max: point2(0.0, 10.0) }, false, |_| {}));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn wrapping_culler_non_finite() {
+    let mut culler = WrappingCuller::new(euclid::size2(100.0, 100.0));
+    culler.add(&Box2D { min: point2(0.0, 0.0), max: point2(f32::INFINITY, 10.0) }, false, |_| {});
+}
+
+#[test]
+#[cfg(not(debug_assertions))]
+fn wrapping_culler_non_finite() {
+    let mut culler = WrappingCuller::new(euclid::size2(100.0, 100.0));
+    assert!(!culler.add(&Box2D { min: point2(0.0, 0.0), max: point2(f32::NAN, 10.0) }, false, |_| {}));
+}
+
+#[test]
+fn occlusion_breakdown() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 50.0) }, true, 1);
+    builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 2);
+
+    let mut breakdown = Vec::new();
+    builder.occlusion_breakdown(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, &mut breakdown);
+    assert_eq!(breakdown, vec![(0, 5000.0), (1, 2500.0)]);
+}
+
+#[test]
+fn clip_stack() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.push_clip(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) });
+    builder.push_clip(&Box2D { min: point2(50.0, 0.0), max: point2(200.0, 100.0) });
+    assert!(builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 50.0) }, true, 0));
+    assert!(!builder.add(&Box2D { min: point2(0.0, 60.0), max: point2(40.0, 70.0) }, true, 1));
+
+    builder.push_clip(&Box2D { min: point2(150.0, 0.0), max: point2(200.0, 100.0) });
+    assert!(!builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, true, 2));
+    builder.pop_clip();
+    builder.pop_clip();
+
+    assert!(builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, false, 3));
+    builder.pop_clip();
+
+    assert_eq!(builder.opaque_items()[0].rectangle, Box2D { min: point2(50.0, 0.0), max: point2(100.0, 50.0) });
+    assert_eq!(builder.opaque_items()[0].original, Box2D { min: point2(0.0, 0.0), max: point2(200.0, 50.0) });
+    assert_eq!(builder.alpha_items().len(), 2);
+    assert!(builder.add(&Box2D { min: point2(150.0, 0.0), max: point2(200.0, 100.0) }, false, 4));
+}
+
+#[test]
+fn promotion_benefit() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 0);
+    builder.add(&Box2D { min: point2(50.0, 0.0), max: point2(150.0, 100.0) }, false, 1);
+    builder.add(&Box2D { min: point2(0.0, 50.0), max: point2(50.0, 150.0) }, false, 2);
+
+    assert_eq!(builder.promotion_benefit(0), 5000.0 + 2500.0);
+    assert_eq!(builder.promotion_benefit(1), 0.0);
+    assert_eq!(builder.promotion_benefit(2), 0.0);
+    assert_eq!(builder.promotion_benefit(3), 0.0);
+}
+
+#[test]
+fn update() {
+    let mut builder = BackToFrontBuilder::new();
+    builder.set_retain_commands(true);
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 100.0) }, true, 1);
+    builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 2);
+    builder.build();
+    assert_eq!(builder.alpha_items().len(), 1);
+
+    builder.update(&[
+        (1, Some((Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true))),
+        (2, None),
+        (3, Some((Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, false))),
+    ]);
+
+    let keys: Vec<u64> = builder.commands().iter().map(|cmd| cmd.2).collect();
+    assert_eq!(keys, vec![0, 1, 3]);
+    assert!(builder.alpha_items().iter().all(|item| item.key == 3));
+    assert_eq!(builder.opaque_items().len(), 1);
+
+    // Keys with several rectangles keep the first one.
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(5.0, 5.0) }, false, 0);
+    builder.update(&[(0, Some((Box2D { min: point2(0.0, 0.0), max: point2(200.0, 100.0) }, false)))]);
+    let keys: Vec<u64> = builder.commands().iter().map(|cmd| cmd.2).collect();
+    assert_eq!(keys, vec![0, 1, 3]);
+    assert_eq!(builder.commands()[0].0.max.x, 200.0);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn update_without_retained_commands() {
+    let mut builder = BackToFrontBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 0);
+    builder.build();
+    builder.update(&[(0, None)]);
+}
+
+#[test]
+fn sort_alpha_ties_by_key() {
+    let mut builder = BackToFrontBuilder::new();
+
+    builder.add_with_depth(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, false, 2, 1.0);
+    builder.add_with_depth(&Box2D { min: point2(20.0, 0.0), max: point2(30.0, 10.0) }, false, 1, 1.0);
+    builder.add_with_depth(&Box2D { min: point2(40.0, 0.0), max: point2(50.0, 10.0) }, false, 2, 1.0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) }, false, 0);
+    builder.add_with_depth(&Box2D { min: point2(60.0, 0.0), max: point2(70.0, 10.0) }, false, 4, 0.0);
+    builder.add_with_depth(&Box2D { min: point2(80.0, 0.0), max: point2(90.0, 10.0) }, false, 3, 0.0);
+    builder.build();
+    builder.sort_alpha_ties_by_key();
+    builder.debug_assert_alpha_ordered();
+
+    let keys: Vec<u64> = builder.alpha_items().iter().map(|item| item.key).collect();
+    assert_eq!(keys, vec![1, 2, 2, 0, 3, 4]);
+    assert_eq!(builder.alpha_items()[1].rectangle.min.x, 0.0);
+    assert_eq!(builder.alpha_items()[2].rectangle.min.x, 40.0);
+}
+
+#[test]
+fn fragments_of() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(50.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 1);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 150.0) }, true, 1);
+
+    let fragments: Vec<(Box2D<f32>, bool)> = builder.fragments_of(1).map(|(item, opaque)| (item.rectangle, opaque)).collect();
+    assert_eq!(fragments, vec![
+        (Box2D { min: point2(50.0, 0.0), max: point2(100.0, 100.0) }, true),
+        (Box2D { min: point2(0.0, 100.0), max: point2(100.0, 150.0) }, true),
+        (Box2D { min: point2(50.0, 0.0), max: point2(100.0, 100.0) }, false),
+    ]);
+    assert_eq!(builder.fragments_of(2).count(), 0);
+}
+
+#[test]
+fn epsilon() {
+    struct MilliEpsilon;
+    impl Epsilon for MilliEpsilon {
+        const EPS: f32 = 0.001;
+    }
+
+    let occluder = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+    let sliver = Box2D { min: point2(-0.0005, 0.0), max: point2(100.0005, 100.0) };
+    let neighbor = Box2D { min: point2(99.9995, 0.0), max: point2(150.0, 100.0) };
+
+    let mut exact: FrontToBackBuilder = FrontToBackBuilder::new();
+    exact.add(&occluder, true, 0);
+    assert!(exact.add(&sliver, false, 1));
+    assert_eq!(exact.alpha_items().len(), 2);
+
+    let mut tolerant: FrontToBackBuilder<u64, MilliEpsilon> = FrontToBackBuilder::with_epsilon();
+    tolerant.add(&occluder, true, 0);
+    assert!(!tolerant.add(&sliver, false, 1));
+    assert!(tolerant.add(&neighbor, false, 2));
+    assert_eq!(tolerant.alpha_items().len(), 1);
+    assert_eq!(tolerant.alpha_items()[0].rectangle, neighbor);
+
+    // Edges within the epsilon are snapped, the wider bands are kept whole.
+    let wide = Box2D { min: point2(-10.0, 0.0005), max: point2(110.0, 120.0) };
+    assert!(tolerant.add(&wide, false, 3));
+    let area: f32 = tolerant.alpha_items()[1..].iter().map(|item| item.rectangle.area()).sum();
+    assert!((area - (wide.area() - 10000.0)).abs() < 0.1);
+}
+
+#[test]
+fn instances() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 50.0) }, false, 1);
+
+    let mut instances = Vec::new();
+    let mut keys = Vec::new();
+    write_instances(builder.opaque_items(), &mut instances, Some(&mut keys));
+    write_instances(builder.alpha_items(), &mut instances, Some(&mut keys));
+    assert_eq!(instances, vec![[0.0, 0.0, 100.0, 100.0], [100.0, 0.0, 50.0, 50.0]]);
+    assert_eq!(keys, vec![0, 1]);
+
+    write_instances(builder.alpha_items(), &mut instances, None);
+    assert_eq!(instances.len(), 3);
+    assert_eq!(keys.len(), 2);
+}
+
+#[test]
+fn largest_empty_rect() {
+    let mut builder = FrontToBackBuilder::new();
+    let viewport = Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) };
+
+    assert_eq!(builder.largest_empty_rect(viewport), Some(viewport));
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 60.0) }, true, 0);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, false, 1);
+    builder.add(&Box2D { min: point2(80.0, 60.0), max: point2(100.0, 100.0) }, true, 2);
+    assert_eq!(
+        builder.largest_empty_rect(viewport),
+        Some(Box2D { min: point2(0.0, 60.0), max: point2(80.0, 100.0) }),
+    );
+
+    builder.add(&viewport, true, 3);
+    assert_eq!(builder.largest_empty_rect(viewport), None);
+}
+
+#[test]
+fn item_data() {
+    let mut builder: FrontToBackBuilder<u64, ZeroEpsilon, u16> = FrontToBackBuilder::default();
+
+    builder.add_with_data(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0, 7);
+    builder.add_with_data(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 200.0) }, false, 1, 3);
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(300.0, 100.0) }, false, 2);
+
+    assert_eq!(builder.opaque_items()[0].data, 7);
+    let alpha = builder.alpha_items();
+    assert_eq!(alpha.len(), 3);
+    assert!(alpha.iter().all(|item| item.data == if item.key == 1 { 3 } else { 0 }));
+    assert_eq!(alpha.iter().filter(|item| item.key == 1).count(), 2);
+
+    // Data types without a default value can be used with `add_with_data`.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct Tag(u8);
+    let mut builder: FrontToBackBuilder<u64, ZeroEpsilon, Tag> = FrontToBackBuilder::default();
+    builder.add_with_data(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0, Tag(1));
+    assert!(!builder.test(&Box2D { min: point2(10.0, 10.0), max: point2(20.0, 20.0) }));
+    assert_eq!(builder.opaque_items()[0].data, Tag(1));
+}
+
+#[test]
+fn result_cache() {
+    let mut builder = BackToFrontBuilder::new();
+    builder.set_cache_enabled(true);
+
+    let add_scene = |builder: &mut BackToFrontBuilder, size: f32| {
+        builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(200.0, 200.0) }, false, 0);
+        builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(size, 100.0) }, true, 1);
+    };
+
+    add_scene(&mut builder, 100.0);
+    builder.build();
+    assert_eq!(builder.alpha_items().len(), 2);
+    assert!(builder.commands().is_empty());
+
+    // Tamper with the results to detect whether they are rebuilt.
+    builder.builder.alpha_items.pop();
+    add_scene(&mut builder, 100.0);
+    builder.build();
+    assert_eq!(builder.alpha_items().len(), 1);
+    assert!(builder.commands().is_empty());
+
+    add_scene(&mut builder, 150.0);
+    builder.build();
+    assert_eq!(builder.alpha_items().len(), 2);
+    assert_eq!(builder.opaque_items()[0].rectangle.max.x, 150.0);
+
+    builder.round_output(RoundMode::Nearest);
+    builder.builder.alpha_items.pop();
+    add_scene(&mut builder, 150.0);
+    builder.build();
+    assert_eq!(builder.alpha_items().len(), 2);
+}
+
+#[test]
+fn test_swept() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 1);
+
+    let left = Box2D { min: point2(10.0, 10.0), max: point2(50.0, 50.0) };
+    let right = Box2D { min: point2(210.0, 10.0), max: point2(250.0, 50.0) };
+    assert!(!builder.test_swept(left, left));
+    assert!(!builder.test(&right));
+    // Visible between the two occluders.
+    assert!(builder.test_swept(left, right));
+    assert!(builder.test_swept(right, left));
+}
+
+#[test]
+fn lock() {
+    let mut builder = FrontToBackBuilder::new();
+
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.add(&Box2D { min: point2(200.0, 0.0), max: point2(300.0, 100.0) }, true, 1);
+    builder.lock();
+    assert!(builder.is_locked());
+    assert!(builder.occupancy.is_some());
+
+    assert!(builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(150.0, 100.0) }, false, 2));
+    assert!(builder.add_with_kind(&Box2D { min: point2(0.0, 200.0), max: point2(50.0, 250.0) }, ItemKind::OpaqueNonOccluding, 3));
+    assert_eq!(builder.occluder_set().occluders().len(), 2);
+    assert!(builder.test(&Box2D { min: point2(10.0, 210.0), max: point2(40.0, 240.0) }));
+    assert!(!builder.test(&Box2D { min: point2(210.0, 10.0), max: point2(290.0, 90.0) }));
+    assert_eq!(builder.alpha_items().len(), 1);
+    assert_eq!(builder.opaque_items().len(), 3);
+
+    builder.clear();
+    assert!(!builder.is_locked());
+    assert!(builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn add_opaque_after_lock() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.lock();
+    builder.add(&Box2D { min: point2(0.0, 200.0), max: point2(50.0, 250.0) }, true, 1);
+}
+
+#[test]
+#[cfg(not(debug_assertions))]
+fn add_opaque_after_lock() {
+    let mut builder = FrontToBackBuilder::new();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.lock();
+    assert!(!builder.add(&Box2D { min: point2(0.0, 200.0), max: point2(50.0, 250.0) }, true, 1));
+    assert!(!builder.add_with_kind(&Box2D { min: point2(0.0, 200.0), max: point2(50.0, 250.0) }, ItemKind::OccluderOnly, 2));
+    assert_eq!(builder.opaque_items().len(), 1);
+    assert_eq!(builder.occluder_set().occluders().len(), 1);
+
+    // The rejected rectangle isn't replayed by `rebuild`, which unlocks the builder.
+    let mut builder = FrontToBackBuilder::retained();
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0);
+    builder.lock();
+    assert!(!builder.add(&Box2D { min: point2(0.0, 200.0), max: point2(50.0, 250.0) }, true, 1));
+    assert!(builder.add(&Box2D { min: point2(0.0, 200.0), max: point2(50.0, 250.0) }, false, 2));
+    assert_eq!(builder.commands().len(), 2);
+    assert_eq!(builder.rebuild(), 2);
+    assert_eq!(builder.opaque_items().len(), 1);
+    assert_eq!(builder.alpha_items().len(), 1);
+}
+
+#[test]
+fn typed_units() {
+    struct DevicePixel;
+    type DeviceBox = euclid::Box2D<f32, DevicePixel>;
+
+    let mut builder: TypedFrontToBackBuilder<DevicePixel> = TypedFrontToBackBuilder::default();
+    builder.add(&DeviceBox::new(point2(0.0, 0.0), point2(100.0, 100.0)), true, 0);
+    builder.add(&DeviceBox::new(point2(0.0, 0.0), point2(150.0, 100.0)), false, 1);
+    assert!(!builder.test(&DeviceBox::new(point2(10.0, 10.0), point2(90.0, 90.0))));
+
+    let alpha: &[Item<u64, (), DevicePixel>] = builder.alpha_items();
+    assert_eq!(alpha.len(), 1);
+    assert_eq!(alpha[0].rectangle, DeviceBox::new(point2(100.0, 0.0), point2(150.0, 100.0)));
+
+    let mut builder: BackToFrontBuilder<u64, DevicePixel> = BackToFrontBuilder::default();
+    builder.add(&DeviceBox::new(point2(0.0, 0.0), point2(150.0, 100.0)), false, 1);
+    builder.add(&DeviceBox::new(point2(0.0, 0.0), point2(100.0, 100.0)), true, 0);
+    builder.build();
+    assert_eq!(builder.alpha_items()[0].rectangle, alpha[0].rectangle);
+}
+
+#[test]
+fn integer_scalar() {
+    let mut culler: Culler<i32> = Culler::default();
+    let mut visible = Vec::new();
+
+    culler.add(&Box2D { min: point2(10, 10), max: point2(20, 20) }, true, |_| {});
+    culler.add(&Box2D { min: point2(0, 0), max: point2(30, 20) }, false, |r| visible.push(*r));
+    assert_eq!(visible, vec![
+        Box2D { min: point2(20, 10), max: point2(30, 20) },
+        Box2D { min: point2(0, 0), max: point2(30, 10) },
+        Box2D { min: point2(0, 10), max: point2(10, 20) },
+    ]);
+
+    // Past 2^24, f32 can't tell these edges apart.
+    let big = 1 << 25;
+    let set = OccluderSet::from(vec![Box2D { min: point2(0i64, 0), max: point2(big, 10) }]);
+    assert!(!set.test(&Box2D { min: point2(0, 0), max: point2(big, 10) }));
+    assert_eq!(set.cull(&Box2D { min: point2(0, 0), max: point2(big + 1, 10) }), vec![
+        Box2D { min: point2(big, 0), max: point2(big + 1, 10) },
+    ]);
+}
+
+#[test]
+fn scalar_builder() {
+    let mut builder: ScalarFrontToBackBuilder<i32> = FrontToBackBuilder::default();
+    assert!(builder.add(&Box2D { min: point2(10, 10), max: point2(20, 20) }, true, 0));
+    assert!(builder.add(&Box2D { min: point2(0, 0), max: point2(30, 20) }, false, 1));
+    assert!(!builder.test(&Box2D { min: point2(12, 12), max: point2(18, 18) }));
+    let alpha: Vec<Box2D<i32>> = builder.alpha_items().iter().map(|item| item.rectangle).collect();
+    assert_eq!(alpha, vec![
+        Box2D { min: point2(20, 10), max: point2(30, 20) },
+        Box2D { min: point2(0, 0), max: point2(30, 10) },
+        Box2D { min: point2(0, 10), max: point2(10, 20) },
+    ]);
+    assert_eq!(builder.opaque_area(), 100i128);
+    assert_eq!(builder.occluded_area(&Box2D { min: point2(0, 0), max: point2(15, 15) }), 25);
+    assert_eq!(builder.cull_efficiency(), (1.0 - 600.0 / 700.0) as f32);
+
+    // The transform is applied in f64 for types other than f32.
+    let mut builder: ScalarFrontToBackBuilder<f64> = FrontToBackBuilder::default();
+    builder.set_transform(AxisAlignedTransform::rotation(1));
+    let big = (1u64 << 40) as f64;
+    builder.add(&Box2D { min: point2(0.0, 0.0), max: point2(big, 10.0) }, true, 0);
+    assert!(!builder.test(&Box2D { min: point2(1.0, 0.0), max: point2(big, 10.0) }));
+    assert!(builder.test(&Box2D { min: point2(1.0, 0.0), max: point2(big + 0.5, 10.0) }));
+    assert_eq!(builder.opaque_area(), big * 10.0);
+
+    let mut builder: BackToFrontBuilder<u64, UnknownUnit, i32> = BackToFrontBuilder::default();
+    builder.add(&Box2D { min: point2(0, 0), max: point2(30, 20) }, false, 1);
+    builder.add(&Box2D { min: point2(10, 10), max: point2(20, 20) }, true, 0);
+    builder.build();
+    assert_eq!(builder.alpha_items().len(), 3);
+}
+
+#[test]
+fn i64_extremes() {
+    let (min, max) = (i64::MIN, i64::MAX);
+    let mut builder: ScalarFrontToBackBuilder<i64> = FrontToBackBuilder::default();
+
+    // The widths and areas overflow i64 and are computed in i128.
+    let quarter = Box2D { min: point2(min, min), max: point2(0, 0) };
+    let band = Box2D { min: point2(min, min), max: point2(max, min / 2) };
+    assert!(builder.add(&quarter, true, 0));
+    assert!(builder.add(&band, false, 1));
+    assert_eq!(builder.alpha_items()[0].rectangle, Box2D { min: point2(0, min), max: point2(max, min / 2) });
+    assert_eq!(builder.opaque_area(), 1 << 126);
+    assert_eq!(builder.occluded_area(&band), 1 << 125);
+    assert_eq!(builder.occluded_area(&Box2D { min: point2(-1, -1), max: point2(1, 1) }), 1);
+
+    assert!(!builder.test(&Box2D { min: point2(min, min), max: point2(0, 0) }));
+    assert!(builder.test(&Box2D { min: point2(min, min), max: point2(1, 0) }));
+    assert!(builder.test(&Box2D { min: point2(max - 1, max - 1), max: point2(max, max) }));
+    assert!(!builder.test(&Box2D { min: point2(max, max), max: point2(max, max) }));
 }
 
 #[test]
@@ -0,0 +1,158 @@
+//! Tracing the boundary of a union of axis-aligned rectangles.
+//!
+//! The rectangles are rasterized into a grid made of all of their distinct x and y
+//! coordinates. Each cell is either covered or not, and the edges between a covered
+//! and an uncovered cell are linked into loops.
+
+use crate::Scalar;
+use euclid::default::{Box2D, Point2D};
+use euclid::point2;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Returns the boundary loops of the union of the rectangles.
+///
+/// The interior is on the right side of each edge when following a loop in a y-down
+/// coordinate system, so outer boundaries and holes have opposite windings. Loops don't
+/// repeat their first vertex and only contain corners.
+pub fn rectilinear_outline<T: Scalar>(rects: &[Box2D<T>]) -> Vec<Vec<Point2D<T>>> {
+    let rects: Vec<&Box2D<T>> = rects.iter().filter(|r| !r.is_empty()).collect();
+    if rects.is_empty() {
+        return Vec::new();
+    }
+
+    let mut xs: Vec<T> = rects.iter().flat_map(|r| [r.min.x, r.max.x]).collect();
+    let mut ys: Vec<T> = rects.iter().flat_map(|r| [r.min.y, r.max.y]).collect();
+    for v in [&mut xs, &mut ys] {
+        v.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        v.dedup();
+    }
+
+    let index = |v: &[T], x: T| v.binary_search_by(|a| a.partial_cmp(&x).unwrap_or(Ordering::Equal)).unwrap() as i32;
+
+    // Cells are indexed by their min corner.
+    let w = xs.len() as i32 - 1;
+    let h = ys.len() as i32 - 1;
+    let mut covered = vec![false; (w * h) as usize];
+    for r in &rects {
+        for y in index(&ys, r.min.y)..index(&ys, r.max.y) {
+            for x in index(&xs, r.min.x)..index(&xs, r.max.x) {
+                covered[(y * w + x) as usize] = true;
+            }
+        }
+    }
+    let is_covered = |x: i32, y: i32| x >= 0 && y >= 0 && x < w && y < h && covered[(y * w + x) as usize];
+
+    // Directed unit edges between grid points, with the covered cell on their right.
+    let mut edges: Vec<((i32, i32), (i32, i32))> = Vec::new();
+    for y in 0..=h {
+        for x in 0..w {
+            match (is_covered(x, y - 1), is_covered(x, y)) {
+                (false, true) => edges.push(((x, y), (x + 1, y))),
+                (true, false) => edges.push(((x + 1, y), (x, y))),
+                _ => {}
+            }
+        }
+    }
+    for x in 0..=w {
+        for y in 0..h {
+            match (is_covered(x - 1, y), is_covered(x, y)) {
+                (false, true) => edges.push(((x, y + 1), (x, y))),
+                (true, false) => edges.push(((x, y), (x, y + 1))),
+                _ => {}
+            }
+        }
+    }
+
+    let mut outgoing: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+    for (idx, edge) in edges.iter().enumerate() {
+        outgoing.entry(edge.0).or_default().push(idx);
+    }
+
+    let direction = |idx: usize| {
+        let (from, to) = edges[idx];
+        (to.0 - from.0, to.1 - from.1)
+    };
+
+    let mut used = vec![false; edges.len()];
+    let mut loops = Vec::new();
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+
+        let mut vertices = Vec::new();
+        let mut current = first;
+        loop {
+            used[current] = true;
+            let d = direction(current);
+
+            // Where two loops touch at a corner, turning right keeps them separate. This
+            // pairs incoming and outgoing edges the same way regardless of where the
+            // loop started.
+            let right = (-d.1, d.0);
+            let left = (d.1, -d.0);
+            let candidates = &outgoing[&edges[current].1];
+            let next = [right, d, left]
+                .iter()
+                .find_map(|&preferred| candidates.iter().cloned().find(|&e| direction(e) == preferred))
+                .unwrap();
+
+            if direction(next) != d {
+                let (x, y) = edges[current].1;
+                vertices.push(point2(xs[x as usize], ys[y as usize]));
+            }
+
+            if next == first {
+                break;
+            }
+            current = next;
+        }
+
+        loops.push(vertices);
+    }
+
+    loops
+}
+
+#[test]
+fn single_rect() {
+    let loops = rectilinear_outline(&[Box2D { min: point2(0.0, 0.0), max: point2(10.0, 20.0) }]);
+    assert_eq!(loops.len(), 1);
+    assert_eq!(loops[0].len(), 4);
+    for p in &[point2(0.0, 0.0), point2(10.0, 0.0), point2(10.0, 20.0), point2(0.0, 20.0)] {
+        assert!(loops[0].contains(p));
+    }
+}
+
+#[test]
+fn adjacent_rects() {
+    let loops = rectilinear_outline(&[
+        Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) },
+        Box2D { min: point2(10.0, 0.0), max: point2(20.0, 20.0) },
+    ]);
+    assert_eq!(loops.len(), 1);
+    assert_eq!(loops[0].len(), 6);
+}
+
+#[test]
+fn hole() {
+    let loops = rectilinear_outline(&[
+        Box2D { min: point2(0.0, 0.0), max: point2(30.0, 10.0) },
+        Box2D { min: point2(0.0, 20.0), max: point2(30.0, 30.0) },
+        Box2D { min: point2(0.0, 10.0), max: point2(10.0, 20.0) },
+        Box2D { min: point2(20.0, 10.0), max: point2(30.0, 20.0) },
+    ]);
+    assert_eq!(loops.len(), 2);
+    assert!(loops.iter().all(|l| l.len() == 4));
+}
+
+#[test]
+fn touching_corners() {
+    let loops = rectilinear_outline(&[
+        Box2D { min: point2(0.0, 0.0), max: point2(10.0, 10.0) },
+        Box2D { min: point2(10.0, 10.0), max: point2(20.0, 20.0) },
+    ]);
+    assert_eq!(loops.len(), 2);
+    assert!(loops.iter().all(|l| l.len() == 4));
+}
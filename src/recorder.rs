@@ -0,0 +1,127 @@
+//! Recording and replaying the commands sent to a builder.
+//!
+//! This is useful to capture a scene that is culled incorrectly and reproduce
+//! it deterministically.
+//!
+//! Commands are stored as fixed size little-endian records:
+//!
+//! ```ascii
+//!  +-------+-------+-------+-------+--------+-----+
+//!  | min.x | min.y | max.x | max.y | opaque | key |
+//!  |  f32  |  f32  |  f32  |  f32  |   u8   | u64 |
+//!  +-------+-------+-------+-------+--------+-----+
+//! ```
+
+use crate::FrontToBackBuilder;
+use euclid::default::Box2D;
+use euclid::point2;
+use std::io;
+
+const RECORD_SIZE: usize = 25;
+
+/// Wraps a `FrontToBackBuilder` and writes each added rectangle to an output stream.
+pub struct Recorder<W: io::Write> {
+    builder: FrontToBackBuilder,
+    output: W,
+}
+
+impl<W: io::Write> Recorder<W> {
+    /// Constructor.
+    pub fn new(builder: FrontToBackBuilder, output: W) -> Self {
+        Recorder { builder, output }
+    }
+
+    /// Record the command and add the rectangle to the builder.
+    ///
+    /// See `FrontToBackBuilder::add`.
+    pub fn add(&mut self, rect: &Box2D<f32>, is_opaque: bool, key: u64) -> io::Result<bool> {
+        self.output.write_all(&encode(rect, is_opaque, key))?;
+
+        Ok(self.builder.add(rect, is_opaque, key))
+    }
+
+    /// The wrapped builder.
+    pub fn builder(&self) -> &FrontToBackBuilder {
+        &self.builder
+    }
+
+    /// Returns the wrapped builder and output stream.
+    pub fn into_inner(self) -> (FrontToBackBuilder, W) {
+        (self.builder, self.output)
+    }
+}
+
+/// Add the rectangles recorded by a `Recorder` to the builder.
+///
+/// Returns the number of commands that were replayed.
+pub fn replay(input: &mut dyn io::Read, builder: &mut FrontToBackBuilder) -> io::Result<usize> {
+    let mut count = 0;
+    let mut record = [0u8; RECORD_SIZE];
+    loop {
+        let mut len = 0;
+        while len < RECORD_SIZE {
+            match input.read(&mut record[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        if len == 0 {
+            return Ok(count);
+        }
+
+        if len < RECORD_SIZE {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated record"));
+        }
+
+        let (rect, is_opaque, key) = decode(&record);
+        builder.add(&rect, is_opaque, key);
+        count += 1;
+    }
+}
+
+fn encode(rect: &Box2D<f32>, is_opaque: bool, key: u64) -> [u8; RECORD_SIZE] {
+    let mut record = [0u8; RECORD_SIZE];
+    record[0..4].copy_from_slice(&rect.min.x.to_le_bytes());
+    record[4..8].copy_from_slice(&rect.min.y.to_le_bytes());
+    record[8..12].copy_from_slice(&rect.max.x.to_le_bytes());
+    record[12..16].copy_from_slice(&rect.max.y.to_le_bytes());
+    record[16] = is_opaque as u8;
+    record[17..25].copy_from_slice(&key.to_le_bytes());
+
+    record
+}
+
+fn decode(record: &[u8; RECORD_SIZE]) -> (Box2D<f32>, bool, u64) {
+    let f = |i: usize| f32::from_le_bytes([record[i], record[i + 1], record[i + 2], record[i + 3]]);
+    let mut key = [0u8; 8];
+    key.copy_from_slice(&record[17..25]);
+
+    let rect = Box2D {
+        min: point2(f(0), f(4)),
+        max: point2(f(8), f(12)),
+    };
+
+    (rect, record[16] != 0, u64::from_le_bytes(key))
+}
+
+#[test]
+fn record_and_replay() {
+    let mut recorder = Recorder::new(FrontToBackBuilder::new(), Vec::new());
+
+    recorder.add(&Box2D { min: point2(0.0, 0.0), max: point2(100.0, 100.0) }, true, 0).unwrap();
+    recorder.add(&Box2D { min: point2(50.0, 50.0), max: point2(150.0, 150.0) }, false, 1).unwrap();
+
+    let (recorded, log) = recorder.into_inner();
+    assert_eq!(log.len(), 2 * RECORD_SIZE);
+
+    let mut replayed = FrontToBackBuilder::new();
+    assert_eq!(replay(&mut &log[..], &mut replayed).unwrap(), 2);
+    assert_eq!(replayed.opaque_items(), recorded.opaque_items());
+    assert_eq!(replayed.alpha_items(), recorded.alpha_items());
+
+    let mut truncated = FrontToBackBuilder::new();
+    assert!(replay(&mut &log[..40], &mut truncated).is_err());
+}
@@ -0,0 +1,134 @@
+//! Representative scenes and a helper to run them, for profiling.
+
+use crate::{FrontToBackBuilder, Key};
+use euclid::default::Box2D;
+use euclid::point2;
+
+/// A rectangle to add to a builder, in front-to-back order.
+pub type SceneItem<K = u64> = (Box2D<f32>, bool, K);
+
+/// Add all of the rectangles of the scene to the builder.
+///
+/// Returns the number of rectangles that were at least partially visible.
+pub fn run_scene<K: Key>(builder: &mut FrontToBackBuilder<K>, scene: &[SceneItem<K>]) -> usize {
+    let mut visible = 0;
+    for &(rect, is_opaque, key) in scene {
+        if builder.add(&rect, is_opaque, key) {
+            visible += 1;
+        }
+    }
+
+    visible
+}
+
+/// A grid of `n * n` overlapping opaque tiles in front of as many alpha ones.
+pub fn dense_occluders(n: usize) -> Vec<SceneItem> {
+    let mut scene = Vec::with_capacity(n * n * 2);
+    for &is_opaque in &[true, false] {
+        for y in 0..n {
+            for x in 0..n {
+                let min = point2(x as f32 * 10.0, y as f32 * 10.0);
+                let rect = Box2D {
+                    min,
+                    max: point2(min.x + 15.0, min.y + 15.0),
+                };
+                scene.push((rect, is_opaque, scene.len() as u64));
+            }
+        }
+    }
+
+    scene
+}
+
+/// `n * n` small opaque rectangles spread out in front of large alpha ones.
+pub fn sparse_occluders(n: usize) -> Vec<SceneItem> {
+    let mut scene = Vec::with_capacity(n * n * 2);
+    for y in 0..n {
+        for x in 0..n {
+            let min = point2(x as f32 * 100.0, y as f32 * 100.0);
+            let rect = Box2D {
+                min,
+                max: point2(min.x + 10.0, min.y + 10.0),
+            };
+            scene.push((rect, true, scene.len() as u64));
+        }
+    }
+    for y in 0..n {
+        for x in 0..n {
+            let min = point2(x as f32 * 100.0 + 5.0, y as f32 * 100.0 + 5.0);
+            let rect = Box2D {
+                min,
+                max: point2(min.x + 150.0, min.y + 150.0),
+            };
+            scene.push((rect, false, scene.len() as u64));
+        }
+    }
+
+    scene
+}
+
+/// `n * n` alpha rectangles in front of a single opaque backdrop that covers all of them.
+pub fn giant_backdrop(n: usize) -> Vec<SceneItem> {
+    let mut scene = Vec::with_capacity(n * n + 1);
+    for y in 0..n {
+        for x in 0..n {
+            let min = point2(x as f32 * 20.0, y as f32 * 20.0);
+            let rect = Box2D {
+                min,
+                max: point2(min.x + 30.0, min.y + 30.0),
+            };
+            scene.push((rect, false, scene.len() as u64));
+        }
+    }
+    let size = n as f32 * 20.0 + 10.0;
+    let backdrop = Box2D {
+        min: point2(0.0, 0.0),
+        max: point2(size, size),
+    };
+    scene.push((backdrop, true, scene.len() as u64));
+
+    scene
+}
+
+/// `n * n` opaque rectangles clustered in a corner, in front of as many alpha rectangles
+/// spread over a `n * 100` square.
+pub fn clustered_occluders(n: usize) -> Vec<SceneItem> {
+    let mut scene = Vec::with_capacity(n * n * 2);
+    for y in 0..n {
+        for x in 0..n {
+            let min = point2(x as f32 * 5.0, y as f32 * 5.0);
+            let rect = Box2D {
+                min,
+                max: point2(min.x + 4.0, min.y + 4.0),
+            };
+            scene.push((rect, true, scene.len() as u64));
+        }
+    }
+    for y in 0..n {
+        for x in 0..n {
+            let min = point2(x as f32 * 100.0 + 20.0, y as f32 * 100.0 + 20.0);
+            let rect = Box2D {
+                min,
+                max: point2(min.x + 60.0, min.y + 60.0),
+            };
+            scene.push((rect, false, scene.len() as u64));
+        }
+    }
+
+    scene
+}
+
+#[test]
+fn scenes() {
+    let mut builder = FrontToBackBuilder::new();
+    assert_eq!(run_scene(&mut builder, &giant_backdrop(4)), 17);
+
+    builder.clear();
+    assert_eq!(run_scene(&mut builder, &sparse_occluders(4)), 32);
+
+    builder.clear();
+    assert_eq!(run_scene(&mut builder, &dense_occluders(4)), 16);
+
+    builder.clear();
+    assert_eq!(run_scene(&mut builder, &clustered_occluders(4)), 32);
+}
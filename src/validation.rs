@@ -0,0 +1,211 @@
+//! Slow reference implementation used to validate the output of the builders.
+
+use crate::FrontToBackBuilder;
+use euclid::default::Box2D;
+use euclid::point2;
+
+/// Cull the provided front-to-back commands with `FrontToBackBuilder` and compare the result
+/// against a brute-force reference evaluated on a grid of sample points.
+///
+/// The scene is sampled at the center of each `step`-sized cell of its bounding box. At each
+/// sample, the rectangles that must be visible are the ones containing the sample up to and
+/// including the front-most opaque one. Samples that lie exactly on the edge of an input
+/// rectangle are skipped since the edge could legitimately go either way.
+///
+/// Panics with a description of the first mismatch.
+pub fn verify_against_reference(commands: &[(Box2D<f32>, bool)], step: f32) {
+    assert!(step > 0.0);
+
+    let mut builder = FrontToBackBuilder::new();
+    for (idx, &(rect, is_opaque)) in commands.iter().enumerate() {
+        builder.add(&rect, is_opaque, idx);
+    }
+
+    let mut bounds: Option<Box2D<f32>> = None;
+    for (rect, _) in commands {
+        bounds = Some(bounds.map_or(*rect, |b| b.union(rect)));
+    }
+    let bounds = match bounds {
+        Some(bounds) => bounds,
+        None => return,
+    };
+
+    let on_edge = |v: f32, x_axis: bool| {
+        commands.iter().any(|(r, _)| {
+            if x_axis {
+                v == r.min.x || v == r.max.x
+            } else {
+                v == r.min.y || v == r.max.y
+            }
+        })
+    };
+
+    let mut expected = Vec::new();
+    let mut actual = Vec::new();
+
+    let mut y = bounds.min.y + step * 0.5;
+    while y < bounds.max.y {
+        if on_edge(y, false) {
+            y += step;
+            continue;
+        }
+
+        let mut x = bounds.min.x + step * 0.5;
+        while x < bounds.max.x {
+            if on_edge(x, true) {
+                x += step;
+                continue;
+            }
+
+            let p = point2(x, y);
+
+            expected.clear();
+            for (idx, (rect, is_opaque)) in commands.iter().enumerate() {
+                if rect.contains(p) {
+                    expected.push(idx);
+                    if *is_opaque {
+                        break;
+                    }
+                }
+            }
+
+            actual.clear();
+            let items = builder.opaque_items().iter().chain(builder.alpha_items().iter());
+            for item in items {
+                if item.rectangle.contains(p) {
+                    actual.push(item.key);
+                }
+            }
+            actual.sort_unstable();
+
+            assert_eq!(
+                expected, actual,
+                "visible rectangles at {:?} don't match the reference",
+                p,
+            );
+
+            x += step;
+        }
+
+        y += step;
+    }
+}
+
+/// An entry point for fuzzers.
+///
+/// The input is interpreted as a sequence of 5 bytes records `[x, y, width, height, opaque]`
+/// which are added front-to-back to a `FrontToBackBuilder`, after which the following
+/// invariants are checked:
+///
+/// - Opaque items don't overlap each other.
+/// - Items are contained in the rectangle they were produced from, and are non-empty
+///   unless that rectangle was empty.
+/// - Items don't overlap the opaque items of rectangles that were in front of them.
+///
+/// For example with `cargo fuzz`:
+///
+/// ```ignore
+/// #![no_main]
+/// libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+///     rectangle_occlusion::fuzz_build(data);
+/// });
+/// ```
+pub fn fuzz_build(data: &[u8]) {
+    let rects: Vec<(Box2D<f32>, bool)> = data
+        .chunks_exact(5)
+        .map(|record| {
+            let x = record[0] as f32;
+            let y = record[1] as f32;
+            let rect = Box2D {
+                min: point2(x, y),
+                max: point2(x + record[2] as f32, y + record[3] as f32),
+            };
+            (rect, record[4] & 1 != 0)
+        })
+        .collect();
+
+    let mut builder = FrontToBackBuilder::new();
+    for (idx, &(rect, is_opaque)) in rects.iter().enumerate() {
+        builder.add(&rect, is_opaque, idx);
+    }
+
+    let opaque = builder.opaque_items();
+    for (i, a) in opaque.iter().enumerate() {
+        for b in &opaque[i + 1..] {
+            let overlap = a.rectangle.intersection(&b.rectangle).filter(|r| !r.is_empty());
+            assert!(overlap.is_none(), "opaque items overlap: {:?} {:?}", a, b);
+        }
+    }
+
+    for item in opaque.iter().chain(builder.alpha_items().iter()) {
+        let source = rects[item.key].0;
+        assert!(source.is_empty() || !item.rectangle.is_empty(), "empty item {:?}", item);
+        assert!(source.contains_box(&item.rectangle), "item out of bounds {:?}", item);
+
+        for occluder in opaque {
+            if occluder.key < item.key && !occluder.rectangle.is_empty() {
+                assert!(!occluder.rectangle.intersects(&item.rectangle), "{:?} is not occluded by {:?}", item, occluder);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn random_scene(seed: u64, count: usize) -> Vec<(Box2D<f32>, bool)> {
+    // A simple xorshift generator to avoid depending on a random number crate.
+    let mut state = seed.wrapping_mul(0x9E3779B97F4A7C15) | 1;
+    let mut next = move |max: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % max
+    };
+
+    (0..count)
+        .map(|_| {
+            let x = next(90) as f32;
+            let y = next(90) as f32;
+            let w = 1.0 + next(40) as f32;
+            let h = 1.0 + next(40) as f32;
+            let rect = Box2D {
+                min: point2(x, y),
+                max: point2(x + w, y + h),
+            };
+            (rect, next(2) == 0)
+        })
+        .collect()
+}
+
+#[test]
+fn random_scenes_match_reference() {
+    for seed in 0..50 {
+        verify_against_reference(&random_scene(seed, 30), 1.0);
+    }
+}
+
+#[test]
+fn fuzz_seeds() {
+    // Scenes from the other tests.
+    fuzz_build(&[0, 0, 100, 100, 1, 50, 50, 100, 100, 0]);
+    fuzz_build(&[0, 0, 100, 100, 1, 0, 0, 100, 100, 0, 10, 10, 80, 80, 0]);
+    fuzz_build(&[50, 50, 50, 50, 1, 0, 0, 150, 150, 0, 0, 0, 150, 150, 1]);
+    // Zero-sized and truncated records.
+    fuzz_build(&[10, 10, 0, 0, 1, 10, 10, 5, 0, 1, 0, 0, 20, 20, 0, 1, 2]);
+    fuzz_build(&[]);
+}
+
+#[test]
+fn random_fuzz_inputs() {
+    let mut state = 0x2545F4914F6CDD1Du64;
+    let mut data = Vec::new();
+    for _ in 0..500 {
+        data.clear();
+        for _ in 0..100 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            data.push((state % 64) as u8);
+        }
+        fuzz_build(&data);
+    }
+}